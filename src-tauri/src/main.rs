@@ -2,12 +2,17 @@
 
 mod services;
 
-use std::sync::Mutex;
-use tokio::sync::Mutex as AsyncMutex;
-use services::{DatabaseService, SettingsService, GeminiService};
-use services::database::HistoryResult;
-use services::gemini::{TranscriptionResult, TestApiResult};
-use services::settings::Settings;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+use services::{AudioStore, DatabaseService, SettingsService, GeminiService, ProviderChain};
+use services::ask::{self, HistoryAnswer};
+use services::audio_store;
+use services::database::{revision_source, HistoryQuery, HistoryResult, Label, NewTranscription, Revision, SemanticMatch, Transcription};
+use services::embeddings::{self, Embedder};
+use services::gemini::{EmbeddingTask, ModelInfo, RewriteMode, TranscriptionResult, TestApiResult};
+use services::http;
+use services::text_diff::{self, TextDiff};
+use services::settings::{DictationMode, NetworkSettings, Provider, Settings};
 
 use tauri::{
     Manager,
@@ -23,6 +28,128 @@ pub struct AppState {
     pub db: Mutex<DatabaseService>,
     pub settings: Mutex<SettingsService>,
     pub gemini: AsyncMutex<GeminiService>,  // Async mutex for async operations
+    pub providers: AsyncMutex<ProviderChain>,  // Locked after `gemini`
+    pub audio: Option<AudioStore>,  // `None` if the audio directory couldn't be created
+    pub embedder: Embedder,
+    pub embeddings_wake: Notify,  // Starts the next embedding backfill pass early
+}
+
+/// Apply the audio retention limits. Takes the settings and db locks, so call it without holding them.
+fn prune_audio(state: &AppState) {
+    let Some(audio) = &state.audio else { return };
+    let Ok(retention) = state.settings.lock().map(|settings| settings.get_all().audio_retention) else { return };
+    let Ok(db) = state.db.lock() else { return };
+    if let Err(e) = audio.prune(&db, &retention) {
+        eprintln!("[Visper] Failed to prune stored audio: {}", e);
+    }
+}
+
+/// Permanently delete entries trashed longer than the retention setting, then
+/// recordings only they used. Takes the settings and db locks, so call it without holding them.
+fn purge_trash(state: &AppState) {
+    let Ok(Some(days)) = state.settings.lock().map(|settings| settings.get_all().trash_retention_days) else { return };
+    let purged = match state.db.lock().map(|db| db.purge_trash(days)) {
+        Ok(Ok(purged)) => purged,
+        Ok(Err(e)) => {
+            eprintln!("[Visper] Failed to purge trash: {}", e);
+            return;
+        }
+        Err(_) => return,
+    };
+    if purged > 0 {
+        prune_audio(state);
+    }
+}
+
+/// Embed one batch of history entries that have no vector yet, returning how many
+/// were stored. Does nothing unless semantic search is enabled.
+async fn backfill_embeddings(state: &AppState) -> Result<usize, String> {
+    if !state.settings.lock().map_err(|e| e.to_string())?.get_all().semantic_search {
+        return Ok(0);
+    }
+    let model = state.embedder.model();
+    let pending = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        db.get_unembedded(model, embeddings::BACKFILL_BATCH).map_err(|e| e.to_string())?
+    };
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let texts: Vec<String> = pending.iter().map(|(_, text)| text.clone()).collect();
+    let vectors = {
+        let gemini = state.gemini.lock().await;
+        state.embedder.embed(&gemini, &texts, EmbeddingTask::Document).await?
+    };
+
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    let mut saved = 0;
+    for ((id, text), vector) in pending.iter().zip(&vectors) {
+        // Skipped if the entry was edited or deleted while embedding
+        if db.save_embedding(*id, text, model, vector).map_err(|e| e.to_string())? {
+            saved += 1;
+        }
+    }
+    Ok(saved)
+}
+
+/// Decompressed recording of a history entry, with its MIME type
+fn load_recording(state: &AppState, id: i64) -> Result<(Arc<Vec<u8>>, &'static str), String> {
+    let file_name = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        let recording_id = db.get_transcription(id).map_err(|e| e.to_string())?
+            .ok_or_else(|| "Transcription not found".to_string())?
+            .recording_id;
+        match recording_id {
+            Some(recording_id) => db.get_recording_file(recording_id).map_err(|e| e.to_string())?,
+            None => None,
+        }
+    };
+    match (&state.audio, file_name) {
+        (Some(audio), Some(name)) => audio.load(&name)
+            .map(|bytes| (bytes, audio_store::mime_type(&name)))
+            .map_err(|e| e.to_string()),
+        _ => Err("No recording is stored for this transcription".to_string()),
+    }
+}
+
+/// Serve an entry's recording at `recording://localhost/{id}`. Byte ranges
+/// are honored so the webview's audio element can seek.
+fn recording_response(
+    app: &tauri::AppHandle,
+    request: &tauri::http::Request<Vec<u8>>,
+) -> tauri::http::Response<Vec<u8>> {
+    use tauri::http::{header, Response, StatusCode};
+
+    let state = app.state::<AppState>();
+    let audio = request.uri().path().trim_start_matches('/').parse::<i64>()
+        .map_err(|e| e.to_string())
+        .and_then(|id| load_recording(&state, id));
+    let (audio, mime_type) = match audio {
+        Ok(audio) => audio,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(e.into_bytes())
+                .unwrap_or_default();
+        }
+    };
+
+    let len = audio.len();
+    let range = request.headers().get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| http::byte_range(value, len));
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let response = match range {
+        Some((start, end)) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+            .body(audio[start..=end].to_vec()),
+        None => builder.body(audio.to_vec()),
+    };
+    response.unwrap_or_default()
 }
 
 // === RECORDING COMMANDS ===
@@ -32,44 +159,342 @@ async fn send_audio_data(
     state: tauri::State<'_, AppState>,
     audio_buffer: Vec<u8>,
     mime_type: String,
+    continue_from: Option<i64>,
 ) -> Result<TranscriptionResult, String> {
-    let gemini = state.gemini.lock().await;
-    gemini.transcribe(&audio_buffer, &mime_type).await.map_err(|e| e.to_string())
+    let settings = {
+        let settings = state.settings.lock().map_err(|e| e.to_string())?;
+        settings.get_all()
+    };
+
+    // In continue mode the entry being continued gives the model its context
+    let previous = match continue_from {
+        Some(id) => {
+            let db = state.db.lock().map_err(|e| e.to_string())?;
+            db.get_transcription(id).map_err(|e| e.to_string())?
+        }
+        None => None,
+    };
+    let context = settings.continuation.context_for(previous.as_ref().map(|entry| entry.text.as_str()));
+
+    let mut result = {
+        let gemini = state.gemini.lock().await;
+        let mut providers = state.providers.lock().await;
+        providers.transcribe(&gemini, &audio_buffer, &mime_type, settings.mode, context).await
+    };
+    result.mode = Some(settings.mode);
+
+    // Keep the recording for re-transcription; history_save links it to the entry
+    if let Some(audio) = &state.audio {
+        if result.text.is_some() {
+            result.audio_file = audio.retain(&settings.audio_retention, &audio_buffer, &mime_type);
+        }
+    }
+    Ok(result)
 }
 
 // === HISTORY COMMANDS ===
 
+/// List, search and filter history a page at a time
 #[tauri::command]
-async fn history_get(
+async fn history_query(
     state: tauri::State<'_, AppState>,
-    page: u32,
-    limit: u32,
+    query: HistoryQuery,
 ) -> Result<HistoryResult, String> {
     let db = state.db.lock().map_err(|e| e.to_string())?;
-    db.get_transcriptions(page, limit).map_err(|e| e.to_string())
+    db.query_transcriptions(&query).map_err(|e| e.to_string())
 }
 
+/// History entries closest in meaning to `query`, most similar first
 #[tauri::command]
-async fn history_search(
+async fn history_semantic_search(
     state: tauri::State<'_, AppState>,
     query: String,
-    page: u32,
-    limit: u32,
-) -> Result<HistoryResult, String> {
+    k: u32,
+) -> Result<Vec<SemanticMatch>, String> {
+    if !state.settings.lock().map_err(|e| e.to_string())?.get_all().semantic_search {
+        return Err("Semantic search is turned off in settings".to_string());
+    }
+    if query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let vector = {
+        let gemini = state.gemini.lock().await;
+        state.embedder.embed(&gemini, &[query], EmbeddingTask::Query).await?
+            .pop()
+            .ok_or_else(|| "No embedding returned".to_string())?
+    };
     let db = state.db.lock().map_err(|e| e.to_string())?;
-    db.search_transcriptions(&query, page, limit).map_err(|e| e.to_string())
+    db.semantic_search(state.embedder.model(), &vector, k.clamp(1, 50) as usize)
+        .map_err(|e| e.to_string())
+}
+
+/// Answer a question from the history entries most related to it, citing them by id
+#[tauri::command]
+async fn history_ask(
+    state: tauri::State<'_, AppState>,
+    question: String,
+) -> Result<HistoryAnswer, String> {
+    let question = question.trim();
+    if question.is_empty() {
+        return Err("Question cannot be empty".to_string());
+    }
+
+    let mut rankings = Vec::new();
+    {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        let related = db.search_related(question, ask::CANDIDATES).map_err(|e| e.to_string())?;
+        rankings.push(related.iter().map(|item| item.id).collect::<Vec<_>>());
+    }
+    if state.settings.lock().map_err(|e| e.to_string())?.get_all().semantic_search {
+        let embedded = {
+            let gemini = state.gemini.lock().await;
+            state.embedder.embed(&gemini, &[question.to_string()], EmbeddingTask::Query).await
+        };
+        // Keyword matches still give an answer when embedding fails
+        match embedded.map(|mut vectors| vectors.pop()) {
+            Ok(Some(vector)) => {
+                let db = state.db.lock().map_err(|e| e.to_string())?;
+                let similar = db.semantic_search(state.embedder.model(), &vector, ask::CANDIDATES)
+                    .map_err(|e| e.to_string())?;
+                rankings.push(similar.iter().map(|m| m.item.id).collect());
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("[Visper] Failed to embed question: {}", e),
+        }
+    }
+
+    let sources = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        let mut sources = Vec::new();
+        for id in ask::fuse(&rankings, ask::CONTEXT_ENTRIES) {
+            if let Some(item) = db.get_transcription(id).map_err(|e| e.to_string())? {
+                sources.push(item);
+            }
+        }
+        sources
+    };
+    if sources.is_empty() {
+        return Err("No dictations related to this question were found".to_string());
+    }
+
+    let (answer, provider) = {
+        let gemini = state.gemini.lock().await;
+        gemini.ask(question, &ask::context(&sources)).await?
+    };
+    let ids: Vec<i64> = sources.iter().map(|item| item.id).collect();
+    Ok(HistoryAnswer {
+        citations: ask::citations(&answer, &ids),
+        answer,
+        sources,
+        provider: provider.as_str().to_string(),
+    })
+}
+
+#[tauri::command]
+async fn history_languages(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.get_languages().map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 async fn history_save(
     state: tauri::State<'_, AppState>,
     text: String,
+    raw_text: Option<String>,
     duration_seconds: f64,
+    language: Option<String>,
+    provider: Option<String>,
+    mode: Option<DictationMode>,
+    audio_file: Option<String>,
 ) -> Result<i64, String> {
+    let audio_file = audio_file.filter(|name| state.audio.as_ref().is_some_and(|audio| audio.contains(name)));
+    let id = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        let recording_id = match &audio_file {
+            Some(name) => Some(db.add_recording(name).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        db.save_transcription(&NewTranscription {
+            text: &text,
+            raw_text: raw_text.as_deref(),
+            duration_seconds,
+            language: language.as_deref(),
+            provider: provider.as_deref(),
+            mode: mode.map(DictationMode::as_str),
+            recording_id,
+        }).map_err(|e| e.to_string())?
+    };
+
+    if audio_file.is_some() {
+        prune_audio(&state);
+    }
+    state.embeddings_wake.notify_one();
+    Ok(id)
+}
+
+/// Append a continued dictation to the entry it continues
+#[tauri::command]
+async fn history_append(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    text: String,
+    raw_text: Option<String>,
+    duration_seconds: f64,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    let appended = db.append_transcription(id, &text, raw_text.as_deref(), duration_seconds).map_err(|e| e.to_string())?;
+    state.embeddings_wake.notify_one();
+    Ok(appended)
+}
+
+/// What cleanup removed from or changed in the verbatim transcript
+#[tauri::command]
+async fn history_diff(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+) -> Result<TextDiff, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    let entry = db.get_transcription(id).map_err(|e| e.to_string())?
+        .ok_or_else(|| "Transcription not found".to_string())?;
+    let raw_text = entry.raw_text
+        .ok_or_else(|| "This transcription has no verbatim version to compare".to_string())?;
+    Ok(text_diff::diff_words(&raw_text, &entry.text))
+}
+
+/// Replace an entry's text by hand; the previous text is kept as a revision
+#[tauri::command]
+async fn history_update(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    text: String,
+) -> Result<bool, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Transcription text cannot be empty".to_string());
+    }
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    let updated = db.update_transcription(id, text, revision_source::EDIT).map_err(|e| e.to_string())?;
+    state.embeddings_wake.notify_one();
+    Ok(updated)
+}
+
+/// Earlier versions of an entry's text, newest first
+#[tauri::command]
+async fn history_revisions(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+) -> Result<Vec<Revision>, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.get_revisions(id).map_err(|e| e.to_string())
+}
+
+/// Changes from a revision to a later one, or to the entry's current text
+#[tauri::command]
+async fn history_revision_diff(
+    state: tauri::State<'_, AppState>,
+    revision_id: i64,
+    compare_to: Option<i64>,
+) -> Result<TextDiff, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    let revision = db.get_revision(revision_id).map_err(|e| e.to_string())?
+        .ok_or_else(|| "Revision not found".to_string())?;
+    let after = match compare_to {
+        Some(other_id) => db.get_revision(other_id).map_err(|e| e.to_string())?
+            .filter(|other| other.transcription_id == revision.transcription_id)
+            .ok_or_else(|| "Revision not found".to_string())?
+            .text,
+        None => db.get_transcription(revision.transcription_id).map_err(|e| e.to_string())?
+            .ok_or_else(|| "Transcription not found".to_string())?
+            .text,
+    };
+    Ok(text_diff::diff_words(&revision.text, &after))
+}
+
+/// Make a revision the entry's current text again
+#[tauri::command]
+async fn history_restore(
+    state: tauri::State<'_, AppState>,
+    revision_id: i64,
+) -> Result<bool, String> {
     let db = state.db.lock().map_err(|e| e.to_string())?;
-    db.save_transcription(&text, duration_seconds).map_err(|e| e.to_string())
+    let restored = db.restore_revision(revision_id).map_err(|e| e.to_string())?;
+    state.embeddings_wake.notify_one();
+    Ok(restored)
 }
 
+/// Rewrite an entry with the LLM and save the result as a new entry linked to it
+#[tauri::command]
+async fn history_rewrite(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    mode: RewriteMode,
+) -> Result<Transcription, String> {
+    let source = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        db.get_transcription(id).map_err(|e| e.to_string())?
+            .ok_or_else(|| "Transcription not found".to_string())?
+    };
+
+    let language = match mode {
+        RewriteMode::Translate => {
+            let settings = state.settings.lock().map_err(|e| e.to_string())?;
+            Some(settings.get_all().target_language)
+        }
+        _ => source.language.clone(),
+    };
+    let (text, provider) = {
+        let gemini = state.gemini.lock().await;
+        gemini.rewrite(&source.text, mode).await?
+    };
+
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    let new_id = db.save_rewrite(&source, &text, mode.as_str(), language.as_deref(), Some(provider.as_str()))
+        .map_err(|e| e.to_string())?;
+    state.embeddings_wake.notify_one();
+    db.get_transcription(new_id).map_err(|e| e.to_string())?
+        .ok_or_else(|| "Rewritten transcription not found".to_string())
+}
+
+/// Transcribe an entry's stored recording again and save the result as a new entry linked to it
+#[tauri::command]
+async fn history_retranscribe(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    provider: Provider,
+    mode: DictationMode,
+) -> Result<Transcription, String> {
+    let source = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        db.get_transcription(id).map_err(|e| e.to_string())?
+            .ok_or_else(|| "Transcription not found".to_string())?
+    };
+    let (audio, mime_type) = load_recording(&state, id)?;
+
+    let result = {
+        let gemini = state.gemini.lock().await;
+        let providers = state.providers.lock().await;
+        providers.transcribe_single(&gemini, provider, &audio, mime_type, mode).await
+    };
+    if !result.success {
+        return Err(result.error.unwrap_or_else(|| "Transcription failed".to_string()));
+    }
+    let text = result.text.ok_or_else(|| "No speech detected".to_string())?;
+
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    // With keepOriginal the verbatim text belongs to the original, which isn't saved here
+    let raw_text = result.raw_text.filter(|_| result.original_text.is_none());
+    let new_id = db.save_retranscription(
+        &source, &text, raw_text.as_deref(), result.language.as_deref(), Some(provider.as_str()), Some(mode.as_str()),
+    ).map_err(|e| e.to_string())?;
+    state.embeddings_wake.notify_one();
+    db.get_transcription(new_id).map_err(|e| e.to_string())?
+        .ok_or_else(|| "Transcription not found".to_string())
+}
+
+/// Move an entry to the trash; its recording is kept until the trash is emptied
 #[tauri::command]
 async fn history_delete(
     state: tauri::State<'_, AppState>,
@@ -79,14 +504,151 @@ async fn history_delete(
     db.delete_transcription(id).map_err(|e| e.to_string())
 }
 
+/// Move all history to the trash, returning the ids to restore to undo it
 #[tauri::command]
 async fn history_clear(
     state: tauri::State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<Vec<i64>, String> {
     let db = state.db.lock().map_err(|e| e.to_string())?;
     db.clear_history().map_err(|e| e.to_string())
 }
 
+// === TRASH COMMANDS ===
+
+#[tauri::command]
+async fn trash_list(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Transcription>, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.get_trash().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn trash_restore(
+    state: tauri::State<'_, AppState>,
+    ids: Vec<i64>,
+) -> Result<usize, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.restore_transcriptions(&ids).map_err(|e| e.to_string())
+}
+
+/// Permanently delete everything in the trash, along with recordings only it used
+#[tauri::command]
+async fn trash_empty(
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let deleted = {
+        let db = state.db.lock().map_err(|e| e.to_string())?;
+        db.empty_trash().map_err(|e| e.to_string())?
+    };
+    prune_audio(&state);
+    Ok(deleted)
+}
+
+// === TAG & FOLDER COMMANDS ===
+
+#[tauri::command]
+async fn tags_list(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Label>, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.get_tags().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn tag_create(
+    state: tauri::State<'_, AppState>,
+    name: String,
+) -> Result<i64, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.create_tag(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn tag_rename(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    name: String,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.rename_tag(id, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn tag_delete(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.delete_tag(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn history_add_tag(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    name: String,
+) -> Result<i64, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.add_tag(id, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn history_remove_tag(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    tag_id: i64,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.remove_tag(id, tag_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn folders_list(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Label>, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.get_folders().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn folder_create(
+    state: tauri::State<'_, AppState>,
+    name: String,
+) -> Result<i64, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.create_folder(&name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn folder_rename(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    name: String,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.rename_folder(id, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn folder_delete(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.delete_folder(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn history_set_folder(
+    state: tauri::State<'_, AppState>,
+    id: i64,
+    folder_id: Option<i64>,
+) -> Result<bool, String> {
+    let db = state.db.lock().map_err(|e| e.to_string())?;
+    db.set_folder(id, folder_id).map_err(|e| e.to_string())
+}
+
 // === SETTINGS COMMANDS ===
 
 #[tauri::command]
@@ -103,8 +665,38 @@ async fn settings_set(
     key: String,
     value: serde_json::Value,
 ) -> Result<(), String> {
-    let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
-    settings.set(&key, value).map_err(|e| e.to_string())
+    let (updated, client) = {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        // Build the client first so network settings that can't be used are never saved
+        let client = if key == "network" {
+            let network: NetworkSettings = serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+            Some(http::client_for(&settings, &network).map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+        settings.set(&key, value).map_err(|e| e.to_string())?;
+        (settings.get_all(), client)
+    };
+    if key == "audioRetention" {
+        prune_audio(&state);
+    }
+    if key == "trashRetentionDays" {
+        purge_trash(&state);
+    }
+    if key == "semanticSearch" {
+        state.embeddings_wake.notify_one();
+    }
+
+    // Keep the transcription services in sync with the new settings
+    let mut gemini = state.gemini.lock().await;
+    let mut providers = state.providers.lock().await;
+    if let Some(client) = client {
+        gemini.update_client(client.clone());
+        providers.update_client(client);
+    }
+    gemini.update_settings(updated.clone());
+    providers.update_settings(updated);
+    Ok(())
 }
 
 #[tauri::command]
@@ -147,9 +739,26 @@ async fn set_api_key(
 async fn test_api(
     state: tauri::State<'_, AppState>,
     key: Option<String>,
+    model: Option<String>,
+    provider: Option<Provider>,
 ) -> Result<TestApiResult, String> {
-    let gemini = state.gemini.lock().await;
-    gemini.test_connection(key.as_deref()).await.map_err(|e| e.to_string())
+    let provider = provider.unwrap_or(Provider::Gemini);
+    if provider.is_generate_content() {
+        let gemini = state.gemini.lock().await;
+        gemini.test_connection(provider, key.as_deref(), model.as_deref()).await.map_err(|e| e.to_string())
+    } else {
+        let providers = state.providers.lock().await;
+        Ok(providers.test_connection(provider).await)
+    }
+}
+
+#[tauri::command]
+async fn list_models(
+    state: tauri::State<'_, AppState>,
+    refresh: Option<bool>,
+) -> Result<Vec<ModelInfo>, String> {
+    let mut gemini = state.gemini.lock().await;
+    gemini.list_models(refresh.unwrap_or(false)).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -167,6 +776,70 @@ async fn delete_api_key(
     Ok(())
 }
 
+#[tauri::command]
+async fn get_openai_api_key(
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<String>, String> {
+    let settings = state.settings.lock().map_err(|e| e.to_string())?;
+    settings.get_openai_api_key().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_openai_api_key(
+    state: tauri::State<'_, AppState>,
+    key: String,
+) -> Result<SetApiKeyResult, String> {
+    {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        if let Err(e) = settings.set_openai_api_key(&key) {
+            return Ok(SetApiKeyResult { success: false, error: Some(e.to_string()) });
+        }
+    }
+    {
+        let mut providers = state.providers.lock().await;
+        providers.update_openai_api_key(Some(key));
+    }
+
+    Ok(SetApiKeyResult { success: true, error: None })
+}
+
+#[tauri::command]
+async fn delete_openai_api_key(
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    {
+        let settings = state.settings.lock().map_err(|e| e.to_string())?;
+        settings.clear_openai_api_key().map_err(|e| e.to_string())?;
+    }
+    {
+        let mut providers = state.providers.lock().await;
+        providers.update_openai_api_key(None);
+    }
+    Ok(())
+}
+
+/// Store the proxy password and rebuild the HTTP client with it
+#[tauri::command]
+async fn set_proxy_password(
+    state: tauri::State<'_, AppState>,
+    password: Option<String>,
+) -> Result<(), String> {
+    let client = {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        match password.as_deref() {
+            Some(password) => settings.set_proxy_password(password),
+            None => settings.clear_proxy_password(),
+        }.map_err(|e| e.to_string())?;
+        http::client_for(&settings, &settings.get_all().network).map_err(|e| e.to_string())?
+    };
+
+    let mut gemini = state.gemini.lock().await;
+    gemini.update_client(client.clone());
+    let mut providers = state.providers.lock().await;
+    providers.update_client(client);
+    Ok(())
+}
+
 #[tauri::command]
 async fn toggle_favorite(
     state: tauri::State<'_, AppState>,
@@ -290,17 +963,58 @@ fn main() {
                 .expect("Failed to initialize settings service");
             let db = DatabaseService::new()
                 .expect("Failed to initialize database service");
-            let gemini = GeminiService::new(&settings)
+            // A missing CA bundle or broken proxy shouldn't keep the app from starting
+            let client = http::client_for(&settings, &settings.get_all().network)
+                .unwrap_or_else(|e| {
+                    eprintln!("[Visper] Invalid network settings ({}). Using defaults.", e);
+                    http::build_client(&NetworkSettings::default(), None)
+                        .expect("Failed to build HTTP client")
+                });
+            let gemini = GeminiService::new(&settings, client.clone())
                 .expect("Failed to initialize Gemini service");
+            let providers = ProviderChain::new(&settings, client)
+                .expect("Failed to initialize provider chain");
+            let audio = AudioStore::new()
+                .inspect_err(|e| eprintln!("[Visper] Failed to initialize audio store: {}", e))
+                .ok();
 
             // Create application state
             let state = AppState {
                 db: Mutex::new(db),
                 settings: Mutex::new(settings),
                 gemini: AsyncMutex::new(gemini),
+                providers: AsyncMutex::new(providers),
+                audio,
+                embedder: Embedder::Gemini,
+                embeddings_wake: Notify::new(),
             };
+            purge_trash(&state);
+            prune_audio(&state);
             app.manage(state);
 
+            // Enforce trash and audio retention while the app stays open
+            let handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(audio_store::SWEEP_INTERVAL);
+                let state = handle.state::<AppState>();
+                purge_trash(&state);
+                prune_audio(&state);
+            });
+
+            // Embed entries for semantic search, a batch at a time, as they're added
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let state = handle.state::<AppState>();
+                loop {
+                    match backfill_embeddings(&state).await {
+                        Ok(0) => {}
+                        Ok(_) => continue,
+                        Err(e) => eprintln!("[Visper] Failed to embed history: {}", e),
+                    }
+                    let _ = tokio::time::timeout(embeddings::BACKFILL_INTERVAL, state.embeddings_wake.notified()).await;
+                }
+            });
+
             // Setup system tray
             setup_tray(app)?;
 
@@ -318,21 +1032,54 @@ fn main() {
 
             Ok(())
         })
+        .register_asynchronous_uri_scheme_protocol("recording", |ctx, request, responder| {
+            let app = ctx.app_handle().clone();
+            std::thread::spawn(move || responder.respond(recording_response(&app, &request)));
+        })
         .invoke_handler(tauri::generate_handler![
             send_audio_data,
-            history_get,
-            history_search,
+            history_query,
+            history_semantic_search,
+            history_ask,
+            history_languages,
             history_save,
+            history_append,
+            history_diff,
+            history_update,
+            history_revisions,
+            history_revision_diff,
+            history_restore,
+            history_rewrite,
+            history_retranscribe,
             history_delete,
             history_clear,
+            trash_list,
+            trash_restore,
+            trash_empty,
+            tags_list,
+            tag_create,
+            tag_rename,
+            tag_delete,
+            history_add_tag,
+            history_remove_tag,
+            folders_list,
+            folder_create,
+            folder_rename,
+            folder_delete,
+            history_set_folder,
             settings_get,
             settings_set,
             get_api_key,
             set_api_key,
             test_api,
+            list_models,
             is_first_launch,
             complete_setup,
             delete_api_key,
+            get_openai_api_key,
+            set_openai_api_key,
+            delete_openai_api_key,
+            set_proxy_password,
             toggle_favorite,
         ])
         .run(tauri::generate_context!())
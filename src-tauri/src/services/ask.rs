@@ -0,0 +1,133 @@
+// Answering questions about the user's own history. Entries related to the
+// question are found by keyword and, when enabled, by meaning; the best of
+// both go to the LLM as context, and the answer cites them by id.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::services::database::Transcription;
+
+/// Candidates taken from each kind of search
+pub const CANDIDATES: usize = 20;
+
+/// Entries sent to the LLM with the question
+pub const CONTEXT_ENTRIES: usize = 8;
+
+/// Longer entries are cut to this many characters in the context
+const MAX_ENTRY_CHARS: usize = 2000;
+
+/// Dampens the lead of top ranks when merging rankings; 60 is the usual choice
+const FUSION_K: f64 = 60.0;
+
+/// An answer and the history entries it was drawn from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryAnswer {
+    pub answer: String,
+    /// Ids of the entries the answer cites, in order of first mention
+    pub citations: Vec<i64>,
+    /// Entries given to the LLM, most relevant first
+    pub sources: Vec<Transcription>,
+    pub provider: String,
+}
+
+/// Merge rankings of entry ids with reciprocal rank fusion: ids ranked well
+/// by several searches come first. Ties keep the newer entry first.
+pub fn fuse(rankings: &[Vec<i64>], limit: usize) -> Vec<i64> {
+    let mut scores: HashMap<i64, f64> = HashMap::new();
+    for ranking in rankings {
+        for (rank, &id) in ranking.iter().enumerate() {
+            *scores.entry(id).or_default() += 1.0 / (FUSION_K + rank as f64 + 1.0);
+        }
+    }
+    let mut fused: Vec<(i64, f64)> = scores.into_iter().collect();
+    fused.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(b_id.cmp(a_id)));
+    fused.into_iter().take(limit).map(|(id, _)| id).collect()
+}
+
+/// Entries formatted for the prompt, each tagged with the id to cite it by
+pub fn context(entries: &[Transcription]) -> String {
+    entries.iter()
+        .map(|entry| {
+            let text: String = entry.text.chars().take(MAX_ENTRY_CHARS).collect();
+            format!("<entry id=\"{}\" date=\"{}\">\n{}\n</entry>", entry.id, entry.created_at, text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Ids cited as `[#12]` or `[#12, #15]` in the answer, keeping only ones in `known`
+pub fn citations(answer: &str, known: &[i64]) -> Vec<i64> {
+    let mut cited = Vec::new();
+    for group in answer.split('[').skip(1).filter_map(|rest| rest.split_once(']').map(|(group, _)| group)) {
+        for id in group.split(',').filter_map(|item| item.trim().strip_prefix('#')?.parse::<i64>().ok()) {
+            if known.contains(&id) && !cited.contains(&id) {
+                cited.push(id);
+            }
+        }
+    }
+    cited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i64, text: &str) -> Transcription {
+        Transcription {
+            id,
+            text: text.to_string(),
+            duration_seconds: None,
+            tokens_used: None,
+            created_at: "2024-01-01 09:00:00".to_string(),
+            is_favorite: 0,
+            language: None,
+            provider: None,
+            raw_text: None,
+            parent_id: None,
+            rewrite_mode: None,
+            recording_id: None,
+            folder_id: None,
+            mode: None,
+            deleted_at: None,
+            tags: Vec::new(),
+            snippet: None,
+        }
+    }
+
+    #[test]
+    fn fuse_favors_ids_ranked_by_both_searches() {
+        let keyword = vec![1, 2, 3];
+        let semantic = vec![4, 3, 2];
+        // 2 and 3 appear in both; 3 has the better combined rank
+        assert_eq!(fuse(&[keyword, semantic], 10), vec![3, 2, 4, 1]);
+    }
+
+    #[test]
+    fn fuse_breaks_ties_toward_newer_entries() {
+        assert_eq!(fuse(&[vec![5, 9], vec![9, 5]], 10), vec![9, 5]);
+        assert_eq!(fuse(&[vec![5], vec![9]], 10), vec![9, 5]);
+    }
+
+    #[test]
+    fn fuse_with_one_ranking_keeps_its_order() {
+        assert_eq!(fuse(&[vec![7, 3, 9]], 10), vec![7, 3, 9]);
+        assert_eq!(fuse(&[vec![7, 3, 9], vec![]], 2), vec![7, 3]);
+        assert!(fuse(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn citations_keep_known_ids_in_order_of_first_mention() {
+        let answer = "You planned it on Sunday [#12], then moved it [#15, #12]. See also [#99] and [note].";
+        assert_eq!(citations(answer, &[12, 15]), vec![12, 15]);
+        assert_eq!(citations("[#3,#1] [ #2 ]", &[1, 2, 3]), vec![3, 1, 2]);
+        assert!(citations("No sources.", &[1]).is_empty());
+    }
+
+    #[test]
+    fn context_tags_and_truncates_entries() {
+        let long = "a".repeat(MAX_ENTRY_CHARS + 10);
+        let context = context(&[entry(3, "Budget meeting"), entry(4, &long)]);
+        assert!(context.starts_with("<entry id=\"3\" date=\"2024-01-01 09:00:00\">\nBudget meeting\n</entry>\n"));
+        assert!(context.contains(&format!("\n{}\n</entry>", "a".repeat(MAX_ENTRY_CHARS))));
+        assert!(!context.contains(&"a".repeat(MAX_ENTRY_CHARS + 1)));
+    }
+}
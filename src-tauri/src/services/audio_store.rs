@@ -0,0 +1,465 @@
+use anyhow::Result;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use crate::services::DatabaseService;
+use crate::services::settings::AudioRetentionSettings;
+
+/// Recordings are stored gzip-compressed, named `{sha256}.{format}.gz`
+const FILE_EXTENSION: &str = ".gz";
+
+/// Formats recordings are kept in, by the extension that names them
+const FORMATS: &[(&str, &str)] = &[
+    ("wav", "audio/wav"),
+    ("webm", "audio/webm"),
+    ("ogg", "audio/ogg"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("aac", "audio/aac"),
+    ("flac", "audio/flac"),
+    ("aiff", "audio/aiff"),
+];
+
+/// Other names browsers and recorders use for the formats above
+const MIME_ALIASES: &[(&str, &str)] = &[
+    ("audio/x-wav", "wav"),
+    ("audio/wave", "wav"),
+    ("video/webm", "webm"),
+    ("audio/opus", "ogg"),
+    ("audio/mp3", "mp3"),
+    ("audio/m4a", "m4a"),
+    ("audio/x-m4a", "m4a"),
+    ("video/mp4", "m4a"),
+    ("audio/x-flac", "flac"),
+    ("audio/x-aiff", "aiff"),
+];
+
+/// Files no entry references yet may still be waiting for their history entry
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// How often the retention policy is enforced in the background
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Content-addressed recordings on disk, named by the SHA-256 of the audio,
+/// so history entries can be played back and re-transcribed later
+pub struct AudioStore {
+    dir: PathBuf,
+    /// The recording loaded last, so seeking through it in the player doesn't
+    /// decompress it again for every range request
+    last_loaded: Mutex<Option<(String, Arc<Vec<u8>>)>>,
+}
+
+impl AudioStore {
+    pub fn new() -> Result<Self> {
+        let data_dir = dirs::data_local_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find data directory"))?;
+        Self::open(data_dir.join("Visper").join("audio"))
+    }
+
+    fn open(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, last_loaded: Mutex::new(None) })
+    }
+
+    /// Keep a transcribed recording when retention is enabled. Returns the file
+    /// name for its history entry to reference, or `None` if it isn't kept.
+    pub fn retain(&self, settings: &AudioRetentionSettings, audio: &[u8], mime_type: &str) -> Option<String> {
+        if !settings.enabled {
+            return None;
+        }
+        match self.save(audio, mime_type) {
+            Ok(name) => Some(name),
+            Err(e) => {
+                eprintln!("[Visper] Failed to store recording: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Compress and store a recording. Returns the file name to register it by,
+    /// which also records the format; storing the same audio twice yields the same file.
+    pub fn save(&self, audio: &[u8], mime_type: &str) -> Result<String> {
+        let format = format_of(mime_type)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported audio type: {}", mime_type))?;
+        let name = format!("{:x}.{}{}", Sha256::digest(audio), format, FILE_EXTENSION);
+        let path = self.path(&name)?;
+        if path.is_file() {
+            return Ok(name);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(audio)?;
+        // Write under a temporary name so a crash never leaves a truncated file behind the hash
+        let partial = self.dir.join(format!("{}.partial", name));
+        std::fs::write(&partial, encoder.finish()?)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(name)
+    }
+
+    /// Decompressed audio of a stored recording
+    pub fn load(&self, name: &str) -> Result<Arc<Vec<u8>>> {
+        let path = self.path(name)?;
+        let mut last_loaded = self.last_loaded.lock().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        // Names are content hashes, so a cached copy is current while the file exists
+        if let Some((cached, audio)) = last_loaded.as_ref() {
+            if cached == name && path.is_file() {
+                return Ok(audio.clone());
+            }
+        }
+
+        let compressed = std::fs::read(&path)?;
+        let mut audio = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut audio)?;
+        let audio = Arc::new(audio);
+        *last_loaded = Some((name.to_string(), audio.clone()));
+        Ok(audio)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.path(name).is_ok_and(|path| path.is_file())
+    }
+
+    /// Enforce the retention policy: recordings of favorites are kept when
+    /// configured, others are deleted past the age limit, then the oldest
+    /// until the store fits the size cap. Recordings no entry uses any more
+    /// are deleted too.
+    pub fn prune(&self, db: &DatabaseService, settings: &AudioRetentionSettings) -> Result<()> {
+        let mut sizes = self.file_sizes()?;
+        let max_age = settings.max_age_days.map(|days| i64::from(days) * SECONDS_PER_DAY);
+        let mut budget = settings.max_size_mb.saturating_mul(1024 * 1024);
+        let mut removed = 0;
+
+        let (favorites, others): (Vec<_>, Vec<_>) = db.get_recordings()?
+            .into_iter()
+            .partition(|r| settings.keep_favorites && r.is_favorite);
+
+        // Favorites are never deleted but still take up room under the cap
+        for recording in favorites {
+            let size = sizes.remove(&recording.file_name).unwrap_or(0);
+            budget = budget.saturating_sub(size);
+        }
+
+        for recording in others {
+            let size = sizes.remove(&recording.file_name);
+            let keep = match size {
+                // The file is gone, so the row is useless
+                None => false,
+                Some(_) if !recording.is_referenced => false,
+                Some(_) if max_age.is_some_and(|max_age| recording.age_seconds > max_age) => false,
+                Some(size) if size <= budget => {
+                    budget -= size;
+                    true
+                }
+                Some(_) => {
+                    // Newest first, so everything older goes as well
+                    budget = 0;
+                    false
+                }
+            };
+            if keep {
+                continue;
+            }
+
+            db.delete_recording(recording.id)?;
+            if size.is_some() {
+                std::fs::remove_file(self.dir.join(&recording.file_name))?;
+            }
+            removed += 1;
+        }
+
+        // Whatever is left on disk was never registered
+        let now = SystemTime::now();
+        for name in sizes.into_keys() {
+            let path = self.dir.join(&name);
+            let modified = std::fs::metadata(&path)?.modified()?;
+            if now.duration_since(modified).unwrap_or_default() >= ORPHAN_GRACE_PERIOD {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            eprintln!("[Visper] Removed {} stored recordings", removed);
+        }
+        Ok(())
+    }
+
+    fn file_sizes(&self) -> Result<HashMap<String, u64>> {
+        let mut sizes = HashMap::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if self.path(&name).is_ok() {
+                sizes.insert(name, entry.metadata()?.len());
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Path of a stored recording; rejects names that could point outside the store
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let valid = name.strip_suffix(FILE_EXTENSION)
+            .and_then(|stem| stem.split_once('.'))
+            .is_some_and(|(hash, format)| {
+                !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit())
+                    && FORMATS.iter().any(|(known, _)| *known == format)
+            });
+        if !valid {
+            anyhow::bail!("Invalid audio file name: {}", name);
+        }
+        Ok(self.dir.join(name))
+    }
+}
+
+/// MIME type of a stored recording, from the format in its file name
+pub fn mime_type(name: &str) -> &'static str {
+    let format = name.strip_suffix(FILE_EXTENSION)
+        .and_then(|stem| stem.split_once('.'))
+        .map(|(_, format)| format);
+    FORMATS.iter()
+        .find(|(known, _)| Some(*known) == format)
+        .map(|(_, mime_type)| *mime_type)
+        .unwrap_or("application/octet-stream")
+}
+
+/// Format a recording of this MIME type is stored as, ignoring parameters such as codecs
+fn format_of(mime_type: &str) -> Option<&'static str> {
+    let base = mime_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+    FORMATS.iter()
+        .find(|(_, known)| *known == base)
+        .map(|(format, _)| *format)
+        .or_else(|| MIME_ALIASES.iter().find(|(alias, _)| *alias == base).map(|(_, format)| *format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::database::NewTranscription;
+
+    fn store(dir: &tempfile::TempDir) -> AudioStore {
+        AudioStore::open(dir.path().join("audio")).unwrap()
+    }
+
+    fn retention(enabled: bool) -> AudioRetentionSettings {
+        AudioRetentionSettings { enabled, ..Default::default() }
+    }
+
+    #[test]
+    fn recordings_are_kept_only_when_retention_is_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+
+        assert_eq!(store.retain(&retention(false), b"RIFF audio", "audio/wav"), None);
+        assert!(store.file_sizes().unwrap().is_empty());
+
+        let name = store.retain(&retention(true), b"RIFF audio", "audio/wav").unwrap();
+        assert!(store.contains(&name));
+        assert_eq!(store.load(&name).unwrap().as_slice(), b"RIFF audio");
+    }
+
+    #[test]
+    fn recordings_keep_the_format_they_arrived_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+
+        let webm = store.retain(&retention(true), b"webm audio", "audio/webm;codecs=opus").unwrap();
+        assert!(webm.ends_with(".webm.gz"), "{}", webm);
+        assert_eq!(mime_type(&webm), "audio/webm");
+        let m4a = store.save(b"mp4 audio", "audio/x-m4a").unwrap();
+        assert_eq!(mime_type(&m4a), "audio/mp4");
+        // Recordings from before the format was part of the name were all WAV
+        assert_eq!(mime_type("0a1b.wav.gz"), "audio/wav");
+        assert!(store.path("0a1b.wav.gz").is_ok());
+
+        // Formats a provider couldn't be sent again aren't kept
+        assert!(store.save(b"text", "text/plain").is_err());
+        assert_eq!(store.retain(&retention(true), b"text", "text/plain"), None);
+        assert_eq!(store.file_sizes().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn names_outside_the_store_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        for name in ["../0a1b.wav.gz", "0a1b.exe.gz", ".wav.gz", "0a1b.wav", "0a1b.wav.gz/..", "zz.wav.gz"] {
+            assert!(store.path(name).is_err(), "{}", name);
+            assert!(store.load(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn identical_audio_is_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        let audio: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        let name = store.save(&audio, "audio/wav").unwrap();
+        assert_eq!(name, format!("{:x}.wav.gz", Sha256::digest(&audio)));
+        assert_eq!(store.save(&audio, "audio/wav").unwrap(), name);
+        assert_ne!(store.save(&audio[1..], "audio/wav").unwrap(), name);
+        assert_eq!(store.file_sizes().unwrap().len(), 2);
+        // Compressed on disk, and nothing partial left behind
+        assert!(store.file_sizes().unwrap()[&name] < audio.len() as u64);
+        assert_eq!(std::fs::read_dir(&store.dir).unwrap().count(), 2);
+
+        assert_eq!(store.load(&name).unwrap().as_slice(), audio.as_slice());
+    }
+
+    #[test]
+    fn loads_are_served_from_the_cache_until_the_file_goes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        let first = store.save(b"first recording", "audio/wav").unwrap();
+        let second = store.save(b"second recording", "audio/wav").unwrap();
+
+        let loaded = store.load(&first).unwrap();
+        assert!(Arc::ptr_eq(&loaded, &store.load(&first).unwrap()));
+        assert_eq!(store.load(&second).unwrap().as_slice(), b"second recording");
+        assert_eq!(store.load(&first).unwrap().as_slice(), b"first recording");
+
+        std::fs::remove_file(store.dir.join(&first)).unwrap();
+        assert!(store.load(&first).is_err());
+    }
+
+    /// Incompressible audio of about `kb` kilobytes
+    fn noise(seed: u64, kb: usize) -> Vec<u8> {
+        let mut state = seed * 2 + 1;
+        (0..kb * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// A stored recording registered for a new history entry, returning both ids
+    fn record(store: &AudioStore, db: &DatabaseService, audio: &[u8], days_old: u32) -> (i64, i64, String) {
+        let name = store.save(audio, "audio/webm").unwrap();
+        let recording_id = db.add_recording(&name).unwrap();
+        let id = db.save_transcription(&NewTranscription {
+            text: "Meeting notes",
+            raw_text: None,
+            duration_seconds: 1.0,
+            language: None,
+            provider: None,
+            mode: None,
+            recording_id: Some(recording_id),
+        }).unwrap();
+        backdate(store, recording_id, days_old);
+        (id, recording_id, name)
+    }
+
+    fn backdate(store: &AudioStore, recording_id: i64, days: u32) {
+        let conn = rusqlite::Connection::open(store.dir.with_file_name("visper.db")).unwrap();
+        conn.execute(
+            "UPDATE recordings SET created_at = datetime('now', ?2) WHERE id = ?1",
+            rusqlite::params![recording_id, format!("-{} days", days)],
+        ).unwrap();
+    }
+
+    fn stored(store: &AudioStore) -> Vec<String> {
+        let mut names: Vec<String> = store.file_sizes().unwrap().into_keys().collect();
+        names.sort();
+        names
+    }
+
+    fn sorted(mut names: Vec<String>) -> Vec<String> {
+        names.sort();
+        names
+    }
+
+    fn setup(dir: &tempfile::TempDir) -> (AudioStore, DatabaseService) {
+        (store(dir), DatabaseService::open(&dir.path().join("visper.db")).unwrap())
+    }
+
+    #[test]
+    fn prune_removes_recordings_past_the_age_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, db) = setup(&dir);
+        let (_, _, fresh) = record(&store, &db, b"fresh", 1);
+        let (_, _, old) = record(&store, &db, b"old", 31);
+        let (favorite_id, _, old_favorite) = record(&store, &db, b"old favorite", 90);
+        db.toggle_favorite(favorite_id).unwrap();
+
+        let settings = AudioRetentionSettings { enabled: true, max_age_days: Some(30), ..Default::default() };
+        store.prune(&db, &settings).unwrap();
+        assert_eq!(stored(&store), sorted(vec![fresh.clone(), old_favorite.clone()]));
+        assert_eq!(db.get_recordings().unwrap().len(), 2);
+        assert!(db.get_recordings().unwrap().iter().all(|r| r.file_name != old));
+
+        // Without the exemption favorites age out like the rest
+        let settings = AudioRetentionSettings { keep_favorites: false, ..settings };
+        store.prune(&db, &settings).unwrap();
+        assert_eq!(stored(&store), vec![fresh.clone()]);
+
+        // No age limit keeps everything that fits the cap
+        let (_, _, ancient) = record(&store, &db, b"ancient", 3650);
+        let settings = AudioRetentionSettings { max_age_days: None, ..settings };
+        store.prune(&db, &settings).unwrap();
+        assert_eq!(stored(&store), sorted(vec![fresh, ancient]));
+    }
+
+    #[test]
+    fn prune_removes_the_oldest_recordings_past_the_size_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, db) = setup(&dir);
+        let (_, _, oldest) = record(&store, &db, &noise(1, 600), 3);
+        let (_, _, older) = record(&store, &db, &noise(2, 600), 2);
+        let (_, _, newer) = record(&store, &db, &noise(3, 600), 1);
+        let (_, _, newest) = record(&store, &db, b"tiny", 0);
+
+        let settings = AudioRetentionSettings { enabled: true, max_size_mb: 1, max_age_days: None, keep_favorites: true };
+        store.prune(&db, &settings).unwrap();
+        // The newest fit in 1 MB; once one doesn't, everything older goes too
+        assert_eq!(stored(&store), sorted(vec![newer.clone(), newest.clone()]));
+        assert!(!stored(&store).contains(&oldest) && !stored(&store).contains(&older));
+
+        // A favorite is never removed but counts against the cap
+        let (favorite_id, _, favorite) = record(&store, &db, &noise(4, 700), 5);
+        db.toggle_favorite(favorite_id).unwrap();
+        store.prune(&db, &settings).unwrap();
+        assert_eq!(stored(&store), sorted(vec![favorite, newest]));
+
+        // A cap too large to count in bytes keeps everything
+        let settings = AudioRetentionSettings { max_size_mb: u64::MAX, ..settings };
+        record(&store, &db, &noise(5, 600), 0);
+        store.prune(&db, &settings).unwrap();
+        assert_eq!(stored(&store).len(), 3);
+    }
+
+    #[test]
+    fn prune_keeps_recordings_history_still_uses() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, db) = setup(&dir);
+        let (id, recording_id, kept) = record(&store, &db, b"kept", 0);
+        let (unused_id, _, unused) = record(&store, &db, b"unused", 0);
+        // A re-transcription shares the first recording
+        let source = db.get_transcription(id).unwrap().unwrap();
+        db.save_retranscription(&source, "Meeting notes.", None, None, None, None).unwrap();
+        db.delete_transcription(unused_id).unwrap();
+        db.empty_trash().unwrap();
+
+        // Saved a moment ago, its history entry may not exist yet
+        let pending = store.save(b"pending", "audio/wav").unwrap();
+
+        let settings = AudioRetentionSettings { enabled: true, ..Default::default() };
+        store.prune(&db, &settings).unwrap();
+        assert_eq!(stored(&store), sorted(vec![kept.clone(), pending]));
+        assert!(!stored(&store).contains(&unused));
+        assert_eq!(db.get_recordings().unwrap().iter().map(|r| r.id).collect::<Vec<_>>(), vec![recording_id]);
+
+        // Deleting one entry leaves the recording to the other
+        db.delete_transcription(id).unwrap();
+        db.empty_trash().unwrap();
+        store.prune(&db, &settings).unwrap();
+        assert!(stored(&store).contains(&kept));
+    }
+}
@@ -1,7 +1,273 @@
-use rusqlite::{Connection, params};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use rusqlite::{Connection, OptionalExtension, ToSql, Transaction, params};
+use rusqlite::types::{ToSqlOutput, Value};
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use crate::services::embeddings;
+use crate::services::fuzzy::FuzzyQuery;
+use crate::services::search_query;
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Schema changes in order: `MIGRATIONS[i]` takes the database to `PRAGMA
+/// user_version` `i + 1`. Append new migrations; never change released ones.
+const MIGRATIONS: &[Migration] = &[
+    migration_1_base,
+    migration_2_language,
+    migration_3_provider,
+    migration_4_raw_text,
+    migration_5_rewrites,
+    migration_6_recordings,
+    migration_7_revisions,
+    migration_8_tags_and_folders,
+    migration_9_history_query,
+    migration_10_normalized_search,
+    migration_11_embeddings,
+    migration_12_trash,
+];
+
+fn migration_1_base(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS transcriptions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            text TEXT NOT NULL,
+            duration_seconds REAL,
+            tokens_used INTEGER,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            is_favorite INTEGER DEFAULT 0
+        );
+    "#)?;
+    add_column(tx, "transcriptions", "duration_seconds", "REAL")?;
+    add_column(tx, "transcriptions", "tokens_used", "INTEGER")?;
+    add_column(tx, "transcriptions", "is_favorite", "INTEGER DEFAULT 0")?;
+
+    tx.execute_batch(r#"
+        CREATE INDEX IF NOT EXISTS idx_created_at ON transcriptions(created_at DESC);
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS transcriptions_fts USING fts5(
+            text,
+            content='transcriptions',
+            content_rowid='id'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS transcriptions_ai AFTER INSERT ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(rowid, text) VALUES (new.id, new.text);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS transcriptions_ad AFTER DELETE ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES('delete', old.id, old.text);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS transcriptions_au AFTER UPDATE ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES('delete', old.id, old.text);
+            INSERT INTO transcriptions_fts(rowid, text) VALUES (new.id, new.text);
+        END;
+
+        -- Index rows from before the index existed, and repair any drift
+        INSERT INTO transcriptions_fts(transcriptions_fts) VALUES('rebuild');
+    "#)
+}
+
+fn migration_2_language(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "transcriptions", "language", "TEXT")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS idx_language ON transcriptions(language);")
+}
+
+fn migration_3_provider(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "transcriptions", "provider", "TEXT")
+}
+
+fn migration_4_raw_text(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "transcriptions", "raw_text", "TEXT")
+}
+
+fn migration_5_rewrites(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "transcriptions", "parent_id", "INTEGER")?;
+    add_column(tx, "transcriptions", "rewrite_mode", "TEXT")
+}
+
+fn migration_6_recordings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS recordings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_name TEXT NOT NULL UNIQUE,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+    "#)?;
+    add_column(tx, "transcriptions", "recording_id", "INTEGER REFERENCES recordings(id) ON DELETE SET NULL")?;
+
+    // Recordings used to be referenced by file name
+    if has_column(tx, "transcriptions", "audio_file")? {
+        tx.execute_batch(r#"
+            INSERT OR IGNORE INTO recordings (file_name, created_at)
+                SELECT audio_file, MIN(created_at) FROM transcriptions
+                WHERE audio_file IS NOT NULL GROUP BY audio_file;
+            UPDATE transcriptions
+                SET recording_id = (SELECT id FROM recordings WHERE file_name = audio_file)
+                WHERE audio_file IS NOT NULL;
+            ALTER TABLE transcriptions DROP COLUMN audio_file;
+        "#)?;
+    }
+    Ok(())
+}
+
+fn migration_7_revisions(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "transcriptions", "text_source", "TEXT")?;
+    tx.execute_batch(r#"
+        CREATE TABLE transcription_revisions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            transcription_id INTEGER NOT NULL REFERENCES transcriptions(id) ON DELETE CASCADE,
+            text TEXT NOT NULL,
+            source TEXT NOT NULL,
+            replaced_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX idx_revisions_transcription ON transcription_revisions(transcription_id, id DESC);
+    "#)
+}
+
+fn migration_8_tags_and_folders(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(r#"
+        CREATE TABLE tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        );
+
+        CREATE TABLE transcription_tags (
+            transcription_id INTEGER NOT NULL REFERENCES transcriptions(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (transcription_id, tag_id)
+        ) WITHOUT ROWID;
+
+        CREATE INDEX idx_transcription_tags_tag ON transcription_tags(tag_id);
+
+        CREATE TABLE folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        );
+    "#)?;
+    add_column(tx, "transcriptions", "folder_id", "INTEGER REFERENCES folders(id) ON DELETE SET NULL")?;
+    tx.execute_batch("CREATE INDEX idx_folder ON transcriptions(folder_id);")
+}
+
+fn migration_9_history_query(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "transcriptions", "mode", "TEXT")?;
+    // One index per `SortKey` expression, so keyset pages are index range scans
+    tx.execute_batch(r#"
+        CREATE INDEX idx_mode ON transcriptions(mode);
+        CREATE INDEX idx_duration ON transcriptions(COALESCE(duration_seconds, 0));
+        CREATE INDEX idx_length ON transcriptions(length(text));
+        CREATE INDEX idx_favorites ON transcriptions(created_at) WHERE is_favorite = 1;
+    "#)
+}
+
+fn migration_10_normalized_search(tx: &Transaction) -> rusqlite::Result<()> {
+    // Combining marks count as word characters so a word with diacritics stays one
+    // token, and tokens of the normalized text line up with the original's
+    tx.execute_batch(&format!(r#"
+        DROP TRIGGER IF EXISTS transcriptions_ai;
+        DROP TRIGGER IF EXISTS transcriptions_ad;
+        DROP TRIGGER IF EXISTS transcriptions_au;
+        DROP TABLE transcriptions_fts;
+
+        CREATE VIRTUAL TABLE transcriptions_fts USING fts5(
+            text,
+            content='transcriptions',
+            content_rowid='id',
+            tokenize="unicode61 categories 'L* N* Co Mn'"
+        );
+
+        CREATE VIRTUAL TABLE transcriptions_trigram USING fts5(
+            text,
+            content='transcriptions',
+            content_rowid='id',
+            tokenize='trigram'
+        );
+
+        CREATE TRIGGER transcriptions_ai AFTER INSERT ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(rowid, text) VALUES (new.id, {f}(new.text));
+            INSERT INTO transcriptions_trigram(rowid, text) VALUES (new.id, {f}(new.text));
+        END;
+
+        CREATE TRIGGER transcriptions_ad AFTER DELETE ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES ('delete', old.id, {f}(old.text));
+            INSERT INTO transcriptions_trigram(transcriptions_trigram, rowid, text) VALUES ('delete', old.id, {f}(old.text));
+        END;
+
+        CREATE TRIGGER transcriptions_au AFTER UPDATE OF text ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES ('delete', old.id, {f}(old.text));
+            INSERT INTO transcriptions_trigram(transcriptions_trigram, rowid, text) VALUES ('delete', old.id, {f}(old.text));
+            INSERT INTO transcriptions_fts(rowid, text) VALUES (new.id, {f}(new.text));
+            INSERT INTO transcriptions_trigram(rowid, text) VALUES (new.id, {f}(new.text));
+        END;
+
+        INSERT INTO transcriptions_fts(rowid, text) SELECT id, {f}(text) FROM transcriptions;
+        INSERT INTO transcriptions_trigram(rowid, text) SELECT id, {f}(text) FROM transcriptions;
+    "#, f = search_query::NORMALIZE_FUNCTION))
+}
+
+fn migration_11_embeddings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(r#"
+        CREATE TABLE transcription_embeddings (
+            transcription_id INTEGER PRIMARY KEY REFERENCES transcriptions(id) ON DELETE CASCADE,
+            model TEXT NOT NULL,
+            vector BLOB NOT NULL
+        );
+
+        CREATE INDEX idx_embeddings_model ON transcription_embeddings(model);
+
+        -- A vector of the old text is stale; the backfill embeds the new one
+        CREATE TRIGGER transcriptions_embedding_au AFTER UPDATE OF text ON transcriptions BEGIN
+            DELETE FROM transcription_embeddings WHERE transcription_id = new.id;
+        END;
+    "#)
+}
+
+fn migration_12_trash(tx: &Transaction) -> rusqlite::Result<()> {
+    // Trashed entries leave the search indexes and come back when restored.
+    // Only indexed rows may be deleted from an external-content index.
+    tx.execute_batch(&format!(r#"
+        ALTER TABLE transcriptions ADD COLUMN deleted_at DATETIME;
+        CREATE INDEX idx_trash ON transcriptions(deleted_at) WHERE deleted_at IS NOT NULL;
+
+        DROP TRIGGER transcriptions_ad;
+        DROP TRIGGER transcriptions_au;
+
+        CREATE TRIGGER transcriptions_ad AFTER DELETE ON transcriptions WHEN old.deleted_at IS NULL BEGIN
+            INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES ('delete', old.id, {f}(old.text));
+            INSERT INTO transcriptions_trigram(transcriptions_trigram, rowid, text) VALUES ('delete', old.id, {f}(old.text));
+        END;
+
+        CREATE TRIGGER transcriptions_au AFTER UPDATE OF text, deleted_at ON transcriptions BEGIN
+            INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text)
+                SELECT 'delete', old.id, {f}(old.text) WHERE old.deleted_at IS NULL;
+            INSERT INTO transcriptions_trigram(transcriptions_trigram, rowid, text)
+                SELECT 'delete', old.id, {f}(old.text) WHERE old.deleted_at IS NULL;
+            INSERT INTO transcriptions_fts(rowid, text)
+                SELECT new.id, {f}(new.text) WHERE new.deleted_at IS NULL;
+            INSERT INTO transcriptions_trigram(rowid, text)
+                SELECT new.id, {f}(new.text) WHERE new.deleted_at IS NULL;
+        END;
+    "#, f = search_query::NORMALIZE_FUNCTION))
+}
+
+fn has_column(tx: &Transaction, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns: Vec<String> = stmt.query_map([], |row| row.get(1))?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(columns.iter().any(|c| c == column))
+}
+
+/// Databases from before versioned migrations may already have some of the
+/// columns later migrations add, so those migrations only add what's missing
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    if !has_column(tx, table, column)? {
+        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transcription {
@@ -15,12 +281,408 @@ pub struct Transcription {
     pub created_at: String,
     #[serde(rename = "isFavorite")]
     pub is_favorite: i32,
+    /// ISO 639-1 code of `text`, when known
+    pub language: Option<String>,
+    /// Provider that produced the transcription, e.g. `gemini` or `whisper`
+    pub provider: Option<String>,
+    /// Verbatim transcript before cleanup, when the provider returned one
+    #[serde(rename = "rawText")]
+    pub raw_text: Option<String>,
+    /// Entry this one was rewritten from
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<i64>,
+    /// Rewrite style that produced this entry, e.g. `shorten`
+    #[serde(rename = "rewriteMode")]
+    pub rewrite_mode: Option<String>,
+    /// Stored recording this entry was transcribed from
+    #[serde(rename = "recordingId")]
+    pub recording_id: Option<i64>,
+    #[serde(rename = "folderId")]
+    pub folder_id: Option<i64>,
+    /// Dictation mode the entry was recorded in, e.g. `summarize`
+    pub mode: Option<String>,
+    /// When the entry was moved to the trash
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<String>,
+    /// Tag names, alphabetical
+    pub tags: Vec<String>,
+    /// In search results, the matching part of `text` with each hit between
+    /// `HIGHLIGHT_START` and `HIGHLIGHT_END`
+    pub snippet: Option<String>,
+}
+
+/// Marks where a search hit starts in `Transcription::snippet`
+pub const HIGHLIGHT_START: char = '\u{2}';
+/// Marks where a search hit ends in `Transcription::snippet`
+pub const HIGHLIGHT_END: char = '\u{3}';
+
+/// Columns read by `Transcription::from_row`, in order
+const TRANSCRIPTION_COLUMNS: &str =
+    "id, text, duration_seconds, tokens_used, created_at, is_favorite, language, provider, raw_text, parent_id, rewrite_mode, recording_id, folder_id, mode, deleted_at,
+     (SELECT json_group_array(name) FROM (
+         SELECT tg.name FROM transcription_tags tt JOIN tags tg ON tg.id = tt.tag_id
+         WHERE tt.transcription_id = transcriptions.id ORDER BY tg.name
+     ))";
+
+impl Transcription {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            text: row.get(1)?,
+            duration_seconds: row.get(2)?,
+            tokens_used: row.get(3)?,
+            created_at: row.get(4)?,
+            is_favorite: row.get(5)?,
+            language: row.get(6)?,
+            provider: row.get(7)?,
+            raw_text: row.get(8)?,
+            parent_id: row.get(9)?,
+            rewrite_mode: row.get(10)?,
+            recording_id: row.get(11)?,
+            folder_id: row.get(12)?,
+            mode: row.get(13)?,
+            deleted_at: row.get(14)?,
+            tags: serde_json::from_str(&row.get::<_, String>(15)?).unwrap_or_default(),
+            snippet: None,
+        })
+    }
+}
+
+/// A stored recording with what the retention policy needs to know about it
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub id: i64,
+    pub file_name: String,
+    pub age_seconds: i64,
+    /// Referenced by at least one favorite entry
+    pub is_favorite: bool,
+    /// Referenced by at least one entry
+    pub is_referenced: bool,
+}
+
+/// What produced a version of an entry's text
+pub mod revision_source {
+    pub const DICTATION: &str = "dictation";
+    pub const REWRITE: &str = "rewrite";
+    pub const RETRANSCRIBE: &str = "retranscribe";
+    pub const APPEND: &str = "append";
+    pub const EDIT: &str = "edit";
+    pub const RESTORE: &str = "restore";
+}
+
+/// A version of an entry's text that has since been replaced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: i64,
+    #[serde(rename = "transcriptionId")]
+    pub transcription_id: i64,
+    pub text: String,
+    /// One of `revision_source`, describing how this text came about
+    pub source: String,
+    #[serde(rename = "replacedAt")]
+    pub replaced_at: String,
+}
+
+impl Revision {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            transcription_id: row.get(1)?,
+            text: row.get(2)?,
+            source: row.get(3)?,
+            replaced_at: row.get(4)?,
+        })
+    }
+}
+
+const REVISION_COLUMNS: &str = "id, transcription_id, text, source, replaced_at";
+
+/// A tag or folder with how many entries it holds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Label {
+    pub id: i64,
+    pub name: String,
+    pub count: i64,
+}
+
+impl Label {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            count: row.get(2)?,
+        })
+    }
+}
+
+/// A new dictation to store in history
+#[derive(Debug, Clone, Default)]
+pub struct NewTranscription<'a> {
+    pub text: &'a str,
+    pub raw_text: Option<&'a str>,
+    pub duration_seconds: f64,
+    pub language: Option<&'a str>,
+    pub provider: Option<&'a str>,
+    pub mode: Option<&'a str>,
+    pub recording_id: Option<i64>,
+}
+
+/// What a history query is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    #[default]
+    CreatedAt,
+    Duration,
+    Length,
+    /// Best search match first when descending; newest first without a search
+    Relevance,
+}
+
+impl SortKey {
+    /// SQL expression to order by; each besides relevance has a matching index
+    fn expr(self, searching: bool) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::Duration => "COALESCE(duration_seconds, 0)",
+            Self::Length => "length(text)",
+            Self::Relevance if searching => "hits.relevance",
+            Self::Relevance => "created_at",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Page size when a query doesn't set one
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 200;
+
+/// Most entries a fuzzy search compares against the search words
+const FUZZY_CANDIDATES: i64 = 500;
+
+/// Filters, order and position for one page of history. Unset filters match everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    /// Full-text search over entry text, see `search_query` for the syntax
+    pub search: Option<String>,
+    /// Match `search` items anywhere inside words, for languages written without
+    /// spaces and for partial words. Items need at least three characters.
+    pub substring: bool,
+    /// Tolerate misrecognized and mis-split words in `search`, ranking by
+    /// similarity instead of `sort`
+    pub fuzzy: bool,
+    /// Created at or after this UTC time, `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`
+    pub from: Option<String>,
+    /// Created before this UTC time, same formats as `from`
+    pub to: Option<String>,
+    #[serde(rename = "favoritesOnly")]
+    pub favorites_only: bool,
+    #[serde(rename = "minDuration")]
+    pub min_duration: Option<f64>,
+    #[serde(rename = "maxDuration")]
+    pub max_duration: Option<f64>,
+    pub language: Option<String>,
+    /// Dictation mode, e.g. `translate`
+    pub mode: Option<String>,
+    #[serde(rename = "tagId")]
+    pub tag_id: Option<i64>,
+    #[serde(rename = "folderId")]
+    pub folder_id: Option<i64>,
+    pub sort: SortKey,
+    pub direction: SortDirection,
+    pub limit: u32,
+    /// `nextCursor` of the previous page; `None` for the first page
+    pub cursor: Option<String>,
+}
+
+impl Default for HistoryQuery {
+    fn default() -> Self {
+        Self {
+            search: None,
+            substring: false,
+            fuzzy: false,
+            from: None,
+            to: None,
+            favorites_only: false,
+            min_duration: None,
+            max_duration: None,
+            language: None,
+            mode: None,
+            tag_id: None,
+            folder_id: None,
+            sort: SortKey::default(),
+            direction: SortDirection::default(),
+            limit: DEFAULT_PAGE_SIZE,
+            cursor: None,
+        }
+    }
+}
+
+/// Sort value and id of the last entry on a page. Encoded opaquely so the
+/// frontend only passes it back.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: SortKey,
+    direction: SortDirection,
+    value: CursorValue,
+    id: i64,
+}
+
+/// Numbers must compare equal after the JSON round trip, which serde_json's
+/// `float_roundtrip` feature guarantees
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CursorValue {
+    Number(f64),
+    Text(String),
+}
+
+impl Cursor {
+    /// Position after the entry with this sort value and id
+    fn after(query: &HistoryQuery, value: CursorValue, id: i64) -> Self {
+        Self { sort: query.sort, direction: query.direction, value, id }
+    }
+
+    /// The query's cursor, if it has one that fits its order
+    fn for_query(query: &HistoryQuery) -> Result<Option<Self>> {
+        let Some(cursor) = query.cursor.as_deref().map(Self::decode).transpose()? else {
+            return Ok(None);
+        };
+        if cursor.sort != query.sort || cursor.direction != query.direction {
+            anyhow::bail!("History cursor doesn't match the query's sort order");
+        }
+        Ok(Some(cursor))
+    }
+
+    fn encode(&self) -> Result<String> {
+        Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+    }
+
+    fn decode(cursor: &str) -> Result<Self> {
+        URL_SAFE_NO_PAD.decode(cursor).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid history cursor"))
+    }
+}
+
+impl CursorValue {
+    fn from_sql(value: Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(Self::Number(n as f64)),
+            Value::Real(n) => Ok(Self::Number(n)),
+            Value::Text(text) => Ok(Self::Text(text)),
+            _ => anyhow::bail!("Unexpected sort value"),
+        }
+    }
+}
+
+impl ToSql for CursorValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            Self::Number(n) => n.to_sql(),
+            Self::Text(text) => text.to_sql(),
+        }
+    }
+}
+
+/// `WHERE` conditions for the filters a `HistoryQuery` sets, so unset ones
+/// don't keep SQLite from using an index
+#[derive(Default)]
+struct HistoryConditions {
+    clauses: Vec<&'static str>,
+    params: Vec<(&'static str, Value)>,
+    /// Full-text table a search matches `:fts` against, narrowing the rows to its hits
+    search_table: Option<&'static str>,
+}
+
+impl HistoryConditions {
+    /// `WITH` clause and table expression to select entries from. With a search,
+    /// `hits` holds each match's bm25 relevance (higher is better) and a highlighted snippet.
+    fn source(&self) -> (String, &'static str) {
+        match self.search_table {
+            Some(table) => (
+                format!(
+                    "WITH hits AS (
+                        SELECT rowid AS hit_id, -bm25({t}) AS relevance,
+                            snippet({t}, 0, char(2), char(3), '…', 16) AS snippet
+                        FROM {t} WHERE {t} MATCH :fts
+                    )",
+                    t = table
+                ),
+                "transcriptions JOIN hits ON hits.hit_id = transcriptions.id",
+            ),
+            None => (String::new(), "transcriptions"),
+        }
+    }
+
+    fn searching(&self) -> bool {
+        self.search_table.is_some()
+    }
+
+    fn add(&mut self, clause: &'static str, name: &'static str, value: impl Into<Value>) {
+        self.clauses.push(clause);
+        self.params.push((name, value.into()));
+    }
+
+    fn sql(&self) -> String {
+        if self.clauses.is_empty() {
+            "1".to_string()
+        } else {
+            self.clauses.join(" AND ")
+        }
+    }
+
+    fn params(&self) -> Vec<(&str, &dyn ToSql)> {
+        self.params.iter().map(|(name, value)| (*name, value as &dyn ToSql)).collect()
+    }
+}
+
+fn page_limit(query: &HistoryQuery) -> usize {
+    let limit = match query.limit {
+        0 => DEFAULT_PAGE_SIZE,
+        limit => limit.min(MAX_PAGE_SIZE),
+    };
+    limit as usize
+}
+
+fn validate_label_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() {
+        anyhow::bail!("Name cannot be empty");
+    }
+    Ok(name)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryResult {
     pub items: Vec<Transcription>,
     pub total: i64,
+    /// Set when fuzzy search hit its candidate limit, so more entries than
+    /// `total` may match
+    #[serde(rename = "totalIsLowerBound")]
+    pub total_is_lower_bound: bool,
+    /// Tags across all matches, not just this page, most used first
+    #[serde(rename = "tagCounts")]
+    pub tag_counts: Vec<Label>,
+    /// Pass as `HistoryQuery::cursor` for the next page; `None` on the last page
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<String>,
+}
+
+/// An entry found by meaning, with its cosine similarity to the query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SemanticMatch {
+    #[serde(flatten)]
+    pub item: Transcription,
+    pub similarity: f32,
 }
 
 pub struct DatabaseService {
@@ -36,13 +698,22 @@ impl DatabaseService {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(&db_path)?;
+        Self::open(&db_path)
+    }
+
+    /// Open the database at `db_path` and bring its schema up to date
+    pub(crate) fn open(db_path: &Path) -> Result<Self> {
+        let conn = Connection::open(db_path)?;
 
         // Enable WAL mode for better concurrency
         conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        // Recordings are unlinked from entries through a foreign key
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        // The full-text index triggers call it
+        search_query::register(&conn)?;
 
         let mut service = Self { conn };
-        service.initialize()?;
+        service.migrate(db_path)?;
 
         Ok(service)
     }
@@ -53,165 +724,1708 @@ impl DatabaseService {
         Ok(data_dir.join("Visper").join("visper.db"))
     }
 
-    fn initialize(&mut self) -> Result<()> {
-        // Create main transcriptions table
-        self.conn.execute_batch(r#"
-            CREATE TABLE IF NOT EXISTS transcriptions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                text TEXT NOT NULL,
-                duration_seconds REAL,
-                tokens_used INTEGER,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                is_favorite INTEGER DEFAULT 0
+    /// Bring the schema up to date, backing the database up first when it
+    /// has data and migrations are pending
+    fn migrate(&mut self, db_path: &Path) -> Result<()> {
+        let version: usize = self.conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            anyhow::bail!(
+                "The history database is from a newer version of Visper (schema {}, this build supports {})",
+                version, MIGRATIONS.len()
             );
+        }
+        if version == MIGRATIONS.len() {
+            return Ok(());
+        }
 
-            CREATE INDEX IF NOT EXISTS idx_created_at ON transcriptions(created_at DESC);
-        "#)?;
+        let has_data: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'transcriptions')",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_data {
+            self.backup(db_path, version)?;
+        }
 
-        // Run migrations for existing databases
-        self.migrate_schema()?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction()?;
+            migration(&tx)
+                .map_err(|e| anyhow::anyhow!("Database migration {} failed: {}", index + 1, e))?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+            tx.commit()?;
+        }
 
-        // Create FTS5 virtual table for full-text search
-        self.conn.execute_batch(r#"
-            CREATE VIRTUAL TABLE IF NOT EXISTS transcriptions_fts USING fts5(
-                text,
-                content='transcriptions',
-                content_rowid='id'
-            );
-        "#)?;
+        Ok(())
+    }
+
+    /// Copy the database next to itself as `visper.v{version}.bak`
+    fn backup(&self, db_path: &Path, version: usize) -> Result<()> {
+        let backup_path = db_path.with_extension(format!("v{}.bak", version));
+        if backup_path.exists() {
+            std::fs::remove_file(&backup_path)?;
+        }
+        self.conn.execute("VACUUM INTO ?1", params![backup_path.to_string_lossy()])?;
+        eprintln!("[Visper] Backed up history database to {}", backup_path.display());
+        Ok(())
+    }
 
-        // Create triggers to keep FTS in sync
-        self.conn.execute_batch(r#"
-            CREATE TRIGGER IF NOT EXISTS transcriptions_ai AFTER INSERT ON transcriptions BEGIN
-                INSERT INTO transcriptions_fts(rowid, text) VALUES (new.id, new.text);
-            END;
+    pub fn save_transcription(&self, entry: &NewTranscription) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO transcriptions
+                 (text, raw_text, duration_seconds, language, provider, mode, recording_id, text_source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.text, entry.raw_text, entry.duration_seconds, entry.language, entry.provider, entry.mode,
+                entry.recording_id, revision_source::DICTATION,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
 
-            CREATE TRIGGER IF NOT EXISTS transcriptions_ad AFTER DELETE ON transcriptions BEGIN
-                INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES('delete', old.id, old.text);
-            END;
+    /// Store a rewrite as a new entry linked to the original it came from.
+    /// Rewrites of rewrites link to the same original.
+    pub fn save_rewrite(
+        &self,
+        source: &Transcription,
+        text: &str,
+        rewrite_mode: &str,
+        language: Option<&str>,
+        provider: Option<&str>,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO transcriptions (text, language, provider, parent_id, rewrite_mode, text_source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                text, language, provider, source.parent_id.unwrap_or(source.id), rewrite_mode,
+                revision_source::REWRITE,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
 
-            CREATE TRIGGER IF NOT EXISTS transcriptions_au AFTER UPDATE ON transcriptions BEGIN
-                INSERT INTO transcriptions_fts(transcriptions_fts, rowid, text) VALUES('delete', old.id, old.text);
-                INSERT INTO transcriptions_fts(rowid, text) VALUES (new.id, new.text);
-            END;
-        "#)?;
+    /// Store a new transcription of an entry's recording, linked to the original
+    pub fn save_retranscription(
+        &self,
+        source: &Transcription,
+        text: &str,
+        raw_text: Option<&str>,
+        language: Option<&str>,
+        provider: Option<&str>,
+        mode: Option<&str>,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO transcriptions
+                 (text, raw_text, duration_seconds, language, provider, mode, parent_id, recording_id, text_source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                text, raw_text, source.duration_seconds, language, provider, mode,
+                source.parent_id.unwrap_or(source.id), source.recording_id, revision_source::RETRANSCRIBE,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_transcription(&self, id: i64) -> Result<Option<Transcription>> {
+        let sql = format!("SELECT {} FROM transcriptions WHERE id = ?1 AND deleted_at IS NULL", TRANSCRIPTION_COLUMNS);
+        Ok(self.conn.query_row(&sql, params![id], Transcription::from_row).optional()?)
+    }
+
+    /// Merge a continued dictation into an existing entry: the text is appended
+    /// and the durations are added up. Returns false if the entry is gone.
+    pub fn append_transcription(
+        &self,
+        id: i64,
+        text: &str,
+        raw_text: Option<&str>,
+        duration_seconds: f64,
+    ) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        Self::save_revision(&tx, id)?;
+        // Where only one side has a verbatim transcript, its cleaned text stands in.
+        // The stored recording no longer covers the whole entry, so it is dropped.
+        let changes = tx.execute(
+            "UPDATE transcriptions
+             SET text = text || ' ' || ?2,
+                 text_source = ?5,
+                 recording_id = NULL,
+                 raw_text = CASE WHEN raw_text IS NULL AND ?3 IS NULL THEN NULL
+                                 ELSE COALESCE(raw_text, text) || ' ' || COALESCE(?3, ?2) END,
+                 duration_seconds = COALESCE(duration_seconds, 0) + ?4
+             WHERE id = ?1 AND deleted_at IS NULL",
+            params![id, text, raw_text, duration_seconds, revision_source::APPEND],
+        )?;
+        tx.commit()?;
+        Ok(changes > 0)
+    }
+
+    /// Replace an entry's text, keeping the current text as a revision.
+    /// Returns false if the entry is gone or in the trash.
+    pub fn update_transcription(&self, id: i64, text: &str, source: &str) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        Self::save_revision(&tx, id)?;
+        let changes = tx.execute(
+            "UPDATE transcriptions SET text = ?2, text_source = ?3 WHERE id = ?1 AND deleted_at IS NULL",
+            params![id, text, source],
+        )?;
+        tx.commit()?;
+        Ok(changes > 0)
+    }
 
+    /// Copy a live entry's current text into its revisions
+    fn save_revision(tx: &Transaction, id: i64) -> Result<()> {
+        // Entries from before revisions were tracked have no text_source
+        tx.execute(
+            "INSERT INTO transcription_revisions (transcription_id, text, source)
+             SELECT id, text, COALESCE(text_source, CASE
+                 WHEN rewrite_mode IS NOT NULL THEN ?2
+                 WHEN parent_id IS NOT NULL THEN ?3
+                 ELSE ?4 END)
+             FROM transcriptions WHERE id = ?1 AND deleted_at IS NULL",
+            params![id, revision_source::REWRITE, revision_source::RETRANSCRIBE, revision_source::DICTATION],
+        )?;
         Ok(())
     }
 
-    fn migrate_schema(&mut self) -> Result<()> {
-        // Check existing columns
-        let mut stmt = self.conn.prepare("PRAGMA table_info(transcriptions)")?;
-        let columns: Vec<String> = stmt.query_map([], |row| row.get(1))?
+    /// Earlier versions of an entry's text, newest first
+    pub fn get_revisions(&self, transcription_id: i64) -> Result<Vec<Revision>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM transcription_revisions WHERE transcription_id = ?1 ORDER BY id DESC",
+            REVISION_COLUMNS
+        ))?;
+
+        let revisions = stmt.query_map(params![transcription_id], Revision::from_row)?
             .filter_map(|r| r.ok())
             .collect();
 
-        // Add missing columns
-        if !columns.contains(&"duration_seconds".to_string()) {
-            self.conn.execute("ALTER TABLE transcriptions ADD COLUMN duration_seconds REAL", [])?;
-        }
-        if !columns.contains(&"tokens_used".to_string()) {
-            self.conn.execute("ALTER TABLE transcriptions ADD COLUMN tokens_used INTEGER", [])?;
-        }
-        if !columns.contains(&"is_favorite".to_string()) {
-            self.conn.execute("ALTER TABLE transcriptions ADD COLUMN is_favorite INTEGER DEFAULT 0", [])?;
-        }
+        Ok(revisions)
+    }
 
-        Ok(())
+    pub fn get_revision(&self, id: i64) -> Result<Option<Revision>> {
+        let sql = format!("SELECT {} FROM transcription_revisions WHERE id = ?1", REVISION_COLUMNS);
+        Ok(self.conn.query_row(&sql, params![id], Revision::from_row).optional()?)
     }
 
-    pub fn save_transcription(&self, text: &str, duration_seconds: f64) -> Result<i64> {
-        self.conn.execute(
-            "INSERT INTO transcriptions (text, duration_seconds) VALUES (?1, ?2)",
-            params![text, duration_seconds],
-        )?;
-        Ok(self.conn.last_insert_rowid())
+    /// Make a revision the entry's text again; the text it replaces becomes a revision too
+    pub fn restore_revision(&self, id: i64) -> Result<bool> {
+        match self.get_revision(id)? {
+            Some(revision) => {
+                self.update_transcription(revision.transcription_id, &revision.text, revision_source::RESTORE)
+            }
+            None => Ok(false),
+        }
     }
 
-    pub fn get_transcriptions(&self, page: u32, limit: u32) -> Result<HistoryResult> {
-        let offset = (page.saturating_sub(1)) * limit;
+    /// One page of history matching the query, with the total and tag counts over all matches
+    pub fn query_transcriptions(&self, query: &HistoryQuery) -> Result<HistoryResult> {
+        if query.fuzzy {
+            let search = FuzzyQuery::new(query.search.as_deref().unwrap_or_default());
+            if !search.is_empty() {
+                return self.query_fuzzy(query, &search);
+            }
+        }
+
+        let conditions = self.history_conditions(query)?;
+        let limit = page_limit(query);
+        let cursor = Cursor::for_query(query)?;
+
+        let (with, from) = conditions.source();
 
         let total: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM transcriptions",
-            [],
+            &format!("{} SELECT COUNT(*) FROM {} WHERE {}", with, from, conditions.sql()),
+            conditions.params().as_slice(),
             |row| row.get(0),
         )?;
 
-        let mut stmt = self.conn.prepare(
-            "SELECT id, text, duration_seconds, tokens_used, created_at, is_favorite
-             FROM transcriptions ORDER BY created_at DESC LIMIT ?1 OFFSET ?2"
+        let tag_counts = self.count_tags(
+            &format!("{} SELECT transcriptions.id FROM {} WHERE {}", with, from, conditions.sql()),
+            &conditions.params(),
         )?;
 
-        let items = stmt.query_map(params![limit, offset], |row| {
-            Ok(Transcription {
-                id: row.get(0)?,
-                text: row.get(1)?,
-                duration_seconds: row.get(2)?,
-                tokens_used: row.get(3)?,
-                created_at: row.get(4)?,
-                is_favorite: row.get(5)?,
-            })
-        })?.filter_map(|r| r.ok()).collect();
+        // Keyset pagination: continue strictly after the previous page's last entry,
+        // with the id breaking ties between equal sort values
+        let sort = query.sort.expr(conditions.searching());
+        let (direction, comparison) = match query.direction {
+            SortDirection::Asc => ("ASC", ">"),
+            SortDirection::Desc => ("DESC", "<"),
+        };
+        let mut where_sql = conditions.sql();
+        let mut page_params = conditions.params();
+        if let Some(cursor) = &cursor {
+            where_sql = format!(
+                "{} AND ({sort} {cmp} :cursor_value OR ({sort} = :cursor_value AND transcriptions.id {cmp} :cursor_id))",
+                where_sql, sort = sort, cmp = comparison
+            );
+            page_params.push((":cursor_value", &cursor.value));
+            page_params.push((":cursor_id", &cursor.id));
+        }
+        // One extra row tells whether there is a next page
+        let fetch = limit + 1;
+        page_params.push((":limit", &fetch));
+
+        let snippet = if conditions.searching() { "hits.snippet" } else { "NULL" };
+        let mut stmt = self.conn.prepare(&format!(
+            "{} SELECT {}, {}, {} FROM {} WHERE {}
+             ORDER BY {} {dir}, transcriptions.id {dir} LIMIT :limit",
+            with, TRANSCRIPTION_COLUMNS, sort, snippet, from, where_sql, sort, dir = direction
+        ))?;
+        let mut rows: Vec<(Transcription, Value)> = stmt
+            .query_map(page_params.as_slice(), |row| {
+                let mut item = Transcription::from_row(row)?;
+                item.snippet = row.get(17)?;
+                Ok((item, row.get(16)?))
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
 
-        Ok(HistoryResult { items, total })
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            let (last, value) = rows.last().expect("page is not empty");
+            Some(Cursor::after(query, CursorValue::from_sql(value.clone())?, last.id).encode()?)
+        } else {
+            None
+        };
+        let items = rows.into_iter().map(|(item, _)| item).collect();
+
+        Ok(HistoryResult { items, total, total_is_lower_bound: false, tag_counts, next_cursor })
     }
 
-    pub fn search_transcriptions(&self, query: &str, page: u32, limit: u32) -> Result<HistoryResult> {
-        let offset = (page.saturating_sub(1)) * limit;
+    /// Fuzzy search: entries sharing trigrams with the search words are compared
+    /// against them in full, and the close ones are paged by similarity
+    fn query_fuzzy(&self, query: &HistoryQuery, search: &FuzzyQuery) -> Result<HistoryResult> {
+        let filters = HistoryQuery { search: None, cursor: None, ..query.clone() };
+        let mut conditions = self.history_conditions(&filters)?;
+        let limit = page_limit(query);
+        let cursor = Cursor::for_query(query)?;
 
-        // Escape special FTS5 characters and add wildcards for prefix matching
-        let search_query = format!("\"{}\"*", query.replace('"', "\"\""));
+        // Words too short for trigrams are compared against the newest entries instead
+        let mut trigram_hits = 0;
+        if let Some(candidates) = search.candidates() {
+            // Counted before the filters, which can hide how many hits the limit cut off
+            trigram_hits = self.conn.query_row(
+                "SELECT COUNT(*) FROM (SELECT rowid FROM transcriptions_trigram
+                                       WHERE transcriptions_trigram MATCH ?1 LIMIT ?2)",
+                params![candidates, FUZZY_CANDIDATES],
+                |row| row.get::<_, i64>(0),
+            )?;
+            conditions.add(
+                "id IN (SELECT rowid FROM transcriptions_trigram WHERE transcriptions_trigram MATCH :fuzzy
+                        ORDER BY rank LIMIT :candidates)",
+                ":fuzzy", candidates,
+            );
+        }
+        let mut params = conditions.params();
+        params.push((":candidates", &FUZZY_CANDIDATES));
 
-        let total: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM transcriptions WHERE id IN (SELECT rowid FROM transcriptions_fts WHERE transcriptions_fts MATCH ?1)",
-            params![search_query],
-            |row| row.get(0),
-        )?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM transcriptions WHERE {} ORDER BY created_at DESC LIMIT :candidates",
+            TRANSCRIPTION_COLUMNS, conditions.sql()
+        ))?;
+        let candidates: Vec<Transcription> = stmt.query_map(params.as_slice(), Transcription::from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+        // Entries past the limit were never compared
+        let total_is_lower_bound = trigram_hits >= FUZZY_CANDIDATES || candidates.len() as i64 >= FUZZY_CANDIDATES;
+        let mut matches: Vec<(Transcription, f64)> = candidates.into_iter()
+            .filter_map(|item| search.similarity(&item.text).map(|similarity| (item, similarity)))
+            .collect();
+        matches.sort_by(|(a, a_similarity), (b, b_similarity)| {
+            b_similarity.total_cmp(a_similarity).then(b.id.cmp(&a.id))
+        });
 
-        let mut stmt = self.conn.prepare(
-            "SELECT t.id, t.text, t.duration_seconds, t.tokens_used, t.created_at, t.is_favorite
-             FROM transcriptions t
-             WHERE t.id IN (SELECT rowid FROM transcriptions_fts WHERE transcriptions_fts MATCH ?1)
-             ORDER BY t.created_at DESC LIMIT ?2 OFFSET ?3"
-        )?;
+        let total = matches.len() as i64;
+        let ids = serde_json::to_string(&matches.iter().map(|(item, _)| item.id).collect::<Vec<_>>())?;
+        let tag_counts = self.count_tags("SELECT value FROM json_each(:ids)", &[(":ids", &ids)])?;
 
-        let items = stmt.query_map(params![search_query, limit, offset], |row| {
-            Ok(Transcription {
-                id: row.get(0)?,
-                text: row.get(1)?,
-                duration_seconds: row.get(2)?,
-                tokens_used: row.get(3)?,
-                created_at: row.get(4)?,
-                is_favorite: row.get(5)?,
-            })
-        })?.filter_map(|r| r.ok()).collect();
+        if let Some(cursor) = &cursor {
+            let CursorValue::Number(after) = cursor.value else {
+                anyhow::bail!("Invalid history cursor");
+            };
+            matches.retain(|(item, similarity)| {
+                *similarity < after || (*similarity == after && item.id < cursor.id)
+            });
+        }
+        let next_cursor = if matches.len() > limit {
+            matches.truncate(limit);
+            let (last, similarity) = matches.last().expect("page is not empty");
+            Some(Cursor::after(query, CursorValue::Number(*similarity), last.id).encode()?)
+        } else {
+            None
+        };
+        let items = matches.into_iter().map(|(item, _)| item).collect();
 
-        Ok(HistoryResult { items, total })
+        Ok(HistoryResult { items, total, total_is_lower_bound, tag_counts, next_cursor })
     }
 
-    pub fn delete_transcription(&self, id: i64) -> Result<bool> {
-        let changes = self.conn.execute("DELETE FROM transcriptions WHERE id = ?1", params![id])?;
-        Ok(changes > 0)
+    /// Tags of the entries whose ids `ids_sql` selects, most used first
+    fn count_tags(&self, ids_sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<Label>> {
+        // A leading WITH in `ids_sql` is fine inside IN (...)
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT tg.id, tg.name, COUNT(*) FROM transcription_tags tt
+             JOIN tags tg ON tg.id = tt.tag_id
+             WHERE tt.transcription_id IN ({})
+             GROUP BY tg.id ORDER BY COUNT(*) DESC, tg.name",
+            ids_sql
+        ))?;
+        let tags = stmt.query_map(params, Label::from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(tags)
     }
 
-    pub fn clear_history(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM transcriptions", [])?;
-        self.conn.execute("INSERT INTO transcriptions_fts(transcriptions_fts) VALUES('rebuild')", [])?;
-        Ok(())
+    fn history_conditions(&self, query: &HistoryQuery) -> Result<HistoryConditions> {
+        let mut conditions = HistoryConditions::default();
+        conditions.clauses.push("deleted_at IS NULL");
+
+        if let Some(search) = &query.search {
+            let table = if query.substring { "transcriptions_trigram" } else { "transcriptions_fts" };
+            let search = search_query::parse(search, query.substring);
+            if let Some(matching) = search.matching {
+                conditions.params.push((":fts", matching.into()));
+                conditions.search_table = Some(table);
+            }
+            if let Some(excluding) = search.excluding {
+                let clause = if query.substring {
+                    "id NOT IN (SELECT rowid FROM transcriptions_trigram WHERE transcriptions_trigram MATCH :fts_excluding)"
+                } else {
+                    "id NOT IN (SELECT rowid FROM transcriptions_fts WHERE transcriptions_fts MATCH :fts_excluding)"
+                };
+                conditions.add(clause, ":fts_excluding", excluding);
+            }
+        }
+        if let Some(from) = &query.from {
+            conditions.add("created_at >= :from", ":from", self.normalize_time(from)?);
+        }
+        if let Some(to) = &query.to {
+            conditions.add("created_at < :to", ":to", self.normalize_time(to)?);
+        }
+        if query.favorites_only {
+            // A literal, so the partial favorites index applies
+            conditions.clauses.push("is_favorite = 1");
+        }
+        if let Some(min) = query.min_duration {
+            conditions.add("duration_seconds >= :min_duration", ":min_duration", min);
+        }
+        if let Some(max) = query.max_duration {
+            conditions.add("duration_seconds <= :max_duration", ":max_duration", max);
+        }
+        if let Some(language) = &query.language {
+            conditions.add("language = :language", ":language", language.clone());
+        }
+        if let Some(mode) = &query.mode {
+            conditions.add("mode = :mode", ":mode", mode.clone());
+        }
+        if let Some(tag_id) = query.tag_id {
+            conditions.add(
+                "id IN (SELECT transcription_id FROM transcription_tags WHERE tag_id = :tag_id)",
+                ":tag_id", tag_id,
+            );
+        }
+        if let Some(folder_id) = query.folder_id {
+            conditions.add("folder_id = :folder_id", ":folder_id", folder_id);
+        }
+
+        Ok(conditions)
     }
 
-    pub fn toggle_favorite(&self, id: i64) -> Result<bool> {
-        self.conn.execute(
-            "UPDATE transcriptions SET is_favorite = CASE WHEN is_favorite = 1 THEN 0 ELSE 1 END WHERE id = ?1",
-            params![id],
-        )?;
+    /// A date or date-time in the format `created_at` is stored in, so it compares as text
+    fn normalize_time(&self, time: &str) -> Result<String> {
+        let normalized: Option<String> = self.conn.query_row("SELECT datetime(?1)", params![time], |row| row.get(0))?;
+        normalized.ok_or_else(|| anyhow::anyhow!("Invalid date: {}", time))
+    }
 
-        let is_favorite: i32 = self.conn.query_row(
-            "SELECT is_favorite FROM transcriptions WHERE id = ?1",
-            params![id],
-            |row| row.get(0),
-        )?;
+    pub fn get_tags(&self) -> Result<Vec<Label>> {
+        self.get_labels(
+            "SELECT tg.id, tg.name, COUNT(t.id) FROM tags tg
+             LEFT JOIN transcription_tags tt ON tt.tag_id = tg.id
+             LEFT JOIN transcriptions t ON t.id = tt.transcription_id AND t.deleted_at IS NULL
+             GROUP BY tg.id ORDER BY tg.name"
+        )
+    }
+
+    /// Create a tag, or return the existing one with that name (case-insensitive)
+    pub fn create_tag(&self, name: &str) -> Result<i64> {
+        self.create_label("tags", name)
+    }
+
+    pub fn rename_tag(&self, id: i64, name: &str) -> Result<bool> {
+        self.rename_label("tags", id, name)
+    }
+
+    /// Delete a tag and remove it from every entry
+    pub fn delete_tag(&self, id: i64) -> Result<bool> {
+        let changes = self.conn.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
+        Ok(changes > 0)
+    }
+
+    /// Tag an entry, creating the tag if needed. Returns the tag id.
+    pub fn add_tag(&self, transcription_id: i64, name: &str) -> Result<i64> {
+        self.ensure_in_history(transcription_id)?;
+        let tag_id = self.create_tag(name)?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO transcription_tags (transcription_id, tag_id) VALUES (?1, ?2)",
+            params![transcription_id, tag_id],
+        )?;
+        Ok(tag_id)
+    }
+
+    pub fn remove_tag(&self, transcription_id: i64, tag_id: i64) -> Result<bool> {
+        let changes = self.conn.execute(
+            "DELETE FROM transcription_tags WHERE transcription_id = ?1 AND tag_id = ?2
+             AND transcription_id IN (SELECT id FROM transcriptions WHERE deleted_at IS NULL)",
+            params![transcription_id, tag_id],
+        )?;
+        Ok(changes > 0)
+    }
+
+    pub fn get_folders(&self) -> Result<Vec<Label>> {
+        self.get_labels(
+            "SELECT f.id, f.name, COUNT(t.id) FROM folders f
+             LEFT JOIN transcriptions t ON t.folder_id = f.id AND t.deleted_at IS NULL
+             GROUP BY f.id ORDER BY f.name"
+        )
+    }
+
+    /// Create a folder, or return the existing one with that name (case-insensitive)
+    pub fn create_folder(&self, name: &str) -> Result<i64> {
+        self.create_label("folders", name)
+    }
+
+    pub fn rename_folder(&self, id: i64, name: &str) -> Result<bool> {
+        self.rename_label("folders", id, name)
+    }
+
+    /// Delete a folder; its entries stay in history without a folder
+    pub fn delete_folder(&self, id: i64) -> Result<bool> {
+        let changes = self.conn.execute("DELETE FROM folders WHERE id = ?1", params![id])?;
+        Ok(changes > 0)
+    }
+
+    /// Move an entry into a folder, or out of any folder with `None`.
+    /// Trashed entries stay where they were.
+    pub fn set_folder(&self, transcription_id: i64, folder_id: Option<i64>) -> Result<bool> {
+        if let Some(folder_id) = folder_id {
+            let exists = self.conn
+                .query_row("SELECT 1 FROM folders WHERE id = ?1", params![folder_id], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if !exists {
+                anyhow::bail!("Folder {} not found", folder_id);
+            }
+        }
+        let changes = self.conn.execute(
+            "UPDATE transcriptions SET folder_id = ?2 WHERE id = ?1 AND deleted_at IS NULL",
+            params![transcription_id, folder_id],
+        )?;
+        Ok(changes > 0)
+    }
+
+    /// Up to `limit` entries sharing words with `text`, most relevant first
+    pub fn search_related(&self, text: &str, limit: usize) -> Result<Vec<Transcription>> {
+        let Some(fts) = search_query::any_word(text) else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM transcriptions
+             JOIN (SELECT rowid AS hit_id, bm25(transcriptions_fts) AS score
+                   FROM transcriptions_fts WHERE transcriptions_fts MATCH ?1) hits
+               ON hits.hit_id = transcriptions.id
+             ORDER BY hits.score, transcriptions.id DESC LIMIT ?2",
+            TRANSCRIPTION_COLUMNS
+        ))?;
+        let items = stmt.query_map(params![fts, limit], Transcription::from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(items)
+    }
+
+    /// Entries without a vector from `model`, newest first
+    pub fn get_unembedded(&self, model: &str, limit: usize) -> Result<Vec<(i64, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.id, t.text FROM transcriptions t
+             LEFT JOIN transcription_embeddings e ON e.transcription_id = t.id AND e.model = ?1
+             WHERE e.transcription_id IS NULL AND t.deleted_at IS NULL
+             ORDER BY t.id DESC LIMIT ?2"
+        )?;
+        let pending = stmt.query_map(params![model, limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(pending)
+    }
+
+    /// Store the vector of an entry's text. Skipped if the entry was deleted or
+    /// its text changed since it was read, so no stale vector is kept.
+    pub fn save_embedding(&self, id: i64, text: &str, model: &str, vector: &[f32]) -> Result<bool> {
+        let changes = self.conn.execute(
+            "INSERT OR REPLACE INTO transcription_embeddings (transcription_id, model, vector)
+             SELECT id, ?3, ?4 FROM transcriptions WHERE id = ?1 AND text = ?2",
+            params![id, text, model, embeddings::to_blob(vector)],
+        )?;
+        Ok(changes > 0)
+    }
+
+    /// The `k` entries whose `model` vectors are closest to `query`, best first
+    pub fn semantic_search(&self, model: &str, query: &[f32], k: usize) -> Result<Vec<SemanticMatch>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT e.transcription_id, e.vector FROM transcription_embeddings e
+             JOIN transcriptions t ON t.id = e.transcription_id
+             WHERE e.model = ?1 AND t.deleted_at IS NULL"
+        )?;
+        let mut scored: Vec<(i64, f32)> = stmt.query_map(params![model], |row| {
+                let vector: Vec<u8> = row.get(1)?;
+                Ok((row.get(0)?, embeddings::cosine_similarity(query, &embeddings::from_blob(&vector))))
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        scored.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(b_id.cmp(a_id)));
+        scored.truncate(k);
+
+        let mut matches = Vec::with_capacity(scored.len());
+        for (id, similarity) in scored {
+            if let Some(item) = self.get_transcription(id)? {
+                matches.push(SemanticMatch { item, similarity });
+            }
+        }
+        Ok(matches)
+    }
+
+    fn get_labels(&self, sql: &str) -> Result<Vec<Label>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let labels = stmt.query_map([], Label::from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(labels)
+    }
+
+    fn create_label(&self, table: &str, name: &str) -> Result<i64> {
+        let name = validate_label_name(name)?;
+        self.conn.execute(&format!("INSERT OR IGNORE INTO {} (name) VALUES (?1)", table), params![name])?;
+        Ok(self.conn.query_row(
+            &format!("SELECT id FROM {} WHERE name = ?1", table),
+            params![name],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn rename_label(&self, table: &str, id: i64, name: &str) -> Result<bool> {
+        let name = validate_label_name(name)?;
+        let taken: bool = self.conn.query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM {} WHERE name = ?1 AND id != ?2)", table),
+            params![name, id],
+            |row| row.get(0),
+        )?;
+        if taken {
+            anyhow::bail!("\"{}\" already exists", name);
+        }
+        let changes = self.conn.execute(&format!("UPDATE {} SET name = ?2 WHERE id = ?1", table), params![id, name])?;
+        Ok(changes > 0)
+    }
+
+    /// Distinct languages present in history, most used first
+    pub fn get_languages(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT language FROM transcriptions WHERE language IS NOT NULL AND deleted_at IS NULL
+             GROUP BY language ORDER BY COUNT(*) DESC"
+        )?;
+
+        let languages = stmt.query_map([], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(languages)
+    }
+
+    /// Register a stored recording, returning its id. The same file is registered once.
+    pub fn add_recording(&self, file_name: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO recordings (file_name) VALUES (?1)",
+            params![file_name],
+        )?;
+        Ok(self.conn.query_row(
+            "SELECT id FROM recordings WHERE file_name = ?1",
+            params![file_name],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn get_recording_file(&self, id: i64) -> Result<Option<String>> {
+        Ok(self.conn.query_row(
+            "SELECT file_name FROM recordings WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        ).optional()?)
+    }
+
+    /// All stored recordings, newest first
+    pub fn get_recordings(&self) -> Result<Vec<Recording>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT r.id, r.file_name,
+                    CAST((julianday('now') - julianday(r.created_at)) * 86400 AS INTEGER),
+                    EXISTS(SELECT 1 FROM transcriptions t WHERE t.recording_id = r.id AND t.is_favorite = 1),
+                    EXISTS(SELECT 1 FROM transcriptions t WHERE t.recording_id = r.id)
+             FROM recordings r ORDER BY r.created_at DESC, r.id DESC"
+        )?;
+
+        let recordings = stmt.query_map([], |row| {
+            Ok(Recording {
+                id: row.get(0)?,
+                file_name: row.get(1)?,
+                age_seconds: row.get(2)?,
+                is_favorite: row.get(3)?,
+                is_referenced: row.get(4)?,
+            })
+        })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(recordings)
+    }
+
+    /// Forget a recording; entries that referenced it keep their text
+    pub fn delete_recording(&self, id: i64) -> Result<()> {
+        self.conn.execute("DELETE FROM recordings WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Move an entry to the trash
+    pub fn delete_transcription(&self, id: i64) -> Result<bool> {
+        let changes = self.conn.execute(
+            "UPDATE transcriptions SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?1 AND deleted_at IS NULL",
+            params![id],
+        )?;
+        Ok(changes > 0)
+    }
+
+    /// Move every entry to the trash, returning their ids so it can be undone
+    pub fn clear_history(&self) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare(
+            "UPDATE transcriptions SET deleted_at = CURRENT_TIMESTAMP WHERE deleted_at IS NULL RETURNING id"
+        )?;
+        let ids = stmt.query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    /// Trashed entries, most recently deleted first
+    pub fn get_trash(&self) -> Result<Vec<Transcription>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM transcriptions WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC",
+            TRANSCRIPTION_COLUMNS
+        ))?;
+        let items = stmt.query_map([], Transcription::from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(items)
+    }
+
+    /// Take entries back out of the trash, returning how many were restored
+    pub fn restore_transcriptions(&self, ids: &[i64]) -> Result<usize> {
+        let changes = self.conn.execute(
+            "UPDATE transcriptions SET deleted_at = NULL
+             WHERE deleted_at IS NOT NULL AND id IN (SELECT value FROM json_each(?1))",
+            params![serde_json::to_string(ids)?],
+        )?;
+        Ok(changes)
+    }
+
+    /// Permanently delete everything in the trash
+    pub fn empty_trash(&self) -> Result<usize> {
+        self.purge("deleted_at IS NOT NULL", params![])
+    }
+
+    /// Permanently delete entries that have been in the trash for at least `days` days
+    pub fn purge_trash(&self, days: u32) -> Result<usize> {
+        self.purge("deleted_at <= datetime('now', ?1)", params![format!("-{} days", days)])
+    }
+
+    fn purge(&self, condition: &str, params: &[&dyn ToSql]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        // Rewrites outlive their original
+        tx.execute(
+            &format!("UPDATE transcriptions SET parent_id = NULL WHERE parent_id IN (SELECT id FROM transcriptions WHERE {})", condition),
+            params,
+        )?;
+        let deleted = tx.execute(&format!("DELETE FROM transcriptions WHERE {}", condition), params)?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    pub fn toggle_favorite(&self, id: i64) -> Result<bool> {
+        let is_favorite: Option<i32> = self.conn.query_row(
+            "UPDATE transcriptions SET is_favorite = CASE WHEN is_favorite = 1 THEN 0 ELSE 1 END
+             WHERE id = ?1 AND deleted_at IS NULL RETURNING is_favorite",
+            params![id],
+            |row| row.get(0),
+        ).optional()?;
+
+        match is_favorite {
+            Some(is_favorite) => Ok(is_favorite == 1),
+            None => anyhow::bail!("History entry {} not found", id),
+        }
+    }
+
+    /// Fails unless the entry exists and isn't in the trash
+    fn ensure_in_history(&self, id: i64) -> Result<()> {
+        let live = self.conn
+            .query_row("SELECT 1 FROM transcriptions WHERE id = ?1 AND deleted_at IS NULL", params![id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if !live {
+            anyhow::bail!("History entry {} not found", id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::embeddings::Embedder;
+
+    /// Arabic with tashkeel, and two English entries
+    const ROWS: &[(&str, &str)] = &[
+        ("مَرْحَبًا بِكُمْ في الاجتماع", "2024-01-01 09:00:00"),
+        ("Meeting notes about the budget", "2024-01-02 09:00:00"),
+        ("Call the dentist tomorrow", "2024-01-03 09:00:00"),
+    ];
+
+    fn open(dir: &tempfile::TempDir) -> DatabaseService {
+        DatabaseService::open(&dir.path().join("visper.db")).unwrap()
+    }
+
+    fn entry(text: &str) -> NewTranscription<'_> {
+        NewTranscription {
+            text,
+            raw_text: None,
+            duration_seconds: 1.0,
+            language: None,
+            provider: None,
+            mode: None,
+            recording_id: None,
+        }
+    }
+
+    /// A database left at `version` by an earlier build, holding `ROWS` and
+    /// whatever else that schema could store
+    fn fixture(path: &Path, version: usize) {
+        let mut conn = Connection::open(path).unwrap();
+        search_query::register(&conn).unwrap();
+        for (index, migration) in MIGRATIONS.iter().enumerate().take(version) {
+            let tx = conn.transaction().unwrap();
+            migration(&tx).unwrap();
+            tx.pragma_update(None, "user_version", index + 1).unwrap();
+            tx.commit().unwrap();
+        }
+
+        for (text, created_at) in ROWS {
+            conn.execute(
+                "INSERT INTO transcriptions (text, created_at, is_favorite) VALUES (?1, ?2, 0)",
+                params![text, created_at],
+            ).unwrap();
+        }
+        if version >= 2 {
+            conn.execute("UPDATE transcriptions SET language = 'ar' WHERE id = 1", []).unwrap();
+        }
+        if version >= 6 {
+            conn.execute_batch(r#"
+                INSERT INTO recordings (file_name) VALUES ('meeting.webm.gz');
+                UPDATE transcriptions SET recording_id = 1 WHERE id = 2;
+            "#).unwrap();
+        }
+        if version >= 8 {
+            conn.execute_batch(r#"
+                INSERT INTO tags (name) VALUES ('work');
+                INSERT INTO transcription_tags (transcription_id, tag_id) VALUES (2, 1);
+            "#).unwrap();
+        }
+        if version >= 11 {
+            conn.execute(
+                "INSERT INTO transcription_embeddings (transcription_id, model, vector) VALUES (2, 'm', ?1)",
+                params![embeddings::to_blob(&[1.0, 0.0])],
+            ).unwrap();
+        }
+        if version >= 12 {
+            conn.execute("UPDATE transcriptions SET deleted_at = '2024-02-01 00:00:00' WHERE id = 3", []).unwrap();
+        }
+    }
+
+    /// Tables with their columns in name order, and every index, trigger and
+    /// virtual table with its SQL
+    fn schema(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare(
+            "SELECT type, name, sql FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' ORDER BY type, name"
+        ).unwrap();
+        let objects: Vec<(String, String, Option<String>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        objects.into_iter()
+            .map(|(kind, name, sql)| {
+                let sql = sql.unwrap_or_default();
+                if kind == "table" && !sql.contains("VIRTUAL") {
+                    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", name)).unwrap();
+                    let mut columns: Vec<String> = stmt.query_map([], |row| row.get(1)).unwrap()
+                        .map(|r| r.unwrap())
+                        .collect();
+                    columns.sort();
+                    format!("table {}: {}", name, columns.join(", "))
+                } else {
+                    format!("{} {}: {}", kind, name, sql)
+                }
+            })
+            .collect()
+    }
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+    }
+
+    /// Ids of the entries a full-text index holds
+    fn indexed_ids(conn: &Connection, table: &str) -> Vec<i64> {
+        conn.execute_batch(&format!(
+            "DROP TABLE IF EXISTS temp.indexed;
+             CREATE VIRTUAL TABLE temp.indexed USING fts5vocab('main', '{}', 'instance');",
+            table
+        )).unwrap();
+        let mut stmt = conn.prepare("SELECT DISTINCT doc FROM temp.indexed ORDER BY doc").unwrap();
+        let ids = stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        ids
+    }
+
+    fn matching_ids(conn: &Connection, table: &str, query: &str) -> Vec<i64> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT rowid FROM {table} WHERE {table} MATCH ?1 ORDER BY rowid", table = table
+        )).unwrap();
+        let ids = stmt.query_map(params![query], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        ids
+    }
+
+    fn texts(db: &DatabaseService) -> Vec<String> {
+        let mut stmt = db.conn.prepare("SELECT text FROM transcriptions ORDER BY id").unwrap();
+        let texts = stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        texts
+    }
+
+    fn current_schema() -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        schema(&open(&dir).conn)
+    }
+
+    #[test]
+    fn migrates_every_schema_version() {
+        let expected_schema = current_schema();
+
+        for version in 1..=MIGRATIONS.len() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("visper.db");
+            fixture(&path, version);
+
+            let db = DatabaseService::open(&path)
+                .unwrap_or_else(|e| panic!("migrating from version {}: {}", version, e));
+            let conn = &db.conn;
+
+            assert_eq!(user_version(conn), MIGRATIONS.len(), "from version {}", version);
+            assert_eq!(schema(conn), expected_schema, "from version {}", version);
+            assert_eq!(texts(&db), ROWS.iter().map(|(text, _)| text.to_string()).collect::<Vec<_>>());
+
+            // Both indexes hold the live entries' normalized text
+            let live: Vec<i64> = if version >= 12 { vec![1, 2] } else { vec![1, 2, 3] };
+            assert_eq!(indexed_ids(conn, "transcriptions_fts"), live, "from version {}", version);
+            assert_eq!(indexed_ids(conn, "transcriptions_trigram"), live, "from version {}", version);
+            assert_eq!(matching_ids(conn, "transcriptions_fts", "مرحبا"), vec![1], "from version {}", version);
+            assert_eq!(matching_ids(conn, "transcriptions_fts", "budget"), vec![2], "from version {}", version);
+            assert_eq!(matching_ids(conn, "transcriptions_trigram", "\"udge\""), vec![2], "from version {}", version);
+
+            let extras: (Option<String>, Option<i64>, usize, usize) = conn.query_row(
+                "SELECT (SELECT language FROM transcriptions WHERE id = 1),
+                        (SELECT recording_id FROM transcriptions WHERE id = 2),
+                        (SELECT COUNT(*) FROM transcription_tags),
+                        (SELECT COUNT(*) FROM transcription_embeddings)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            ).unwrap();
+            assert_eq!(extras.0.is_some(), version >= 2, "language from version {}", version);
+            assert_eq!(extras.1.is_some(), version >= 6, "recording from version {}", version);
+            assert_eq!(extras.2, usize::from(version >= 8), "tags from version {}", version);
+            assert_eq!(extras.3, usize::from(version >= 11), "embeddings from version {}", version);
+
+            // The pre-migration copy is kept next to the database
+            let backup = dir.path().join(format!("visper.v{}.bak", version));
+            if version == MIGRATIONS.len() {
+                assert!(!backup.exists(), "no backup without pending migrations");
+            } else {
+                assert!(backup.exists(), "backup from version {}", version);
+                let backup = Connection::open(&backup).unwrap();
+                assert_eq!(user_version(&backup), version);
+                let count: usize = backup.query_row("SELECT COUNT(*) FROM transcriptions", [], |row| row.get(0)).unwrap();
+                assert_eq!(count, ROWS.len());
+            }
+        }
+    }
+
+    #[test]
+    fn migrates_an_unversioned_database() {
+        // History from before versioned migrations: no index, recordings by file name
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("visper.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(r#"
+            CREATE TABLE transcriptions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                text TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                language TEXT,
+                audio_file TEXT
+            );
+            INSERT INTO transcriptions (text, created_at, language, audio_file) VALUES
+                ('first take', '2023-05-01 10:00:00', 'en', 'a.webm'),
+                ('second take', '2023-05-01 10:05:00', 'en', 'a.webm'),
+                ('no recording', '2023-05-02 10:00:00', NULL, NULL);
+        "#).unwrap();
+        drop(conn);
+
+        let db = DatabaseService::open(&path).unwrap();
+        assert_eq!(user_version(&db.conn), MIGRATIONS.len());
+        assert_eq!(schema(&db.conn), current_schema());
+        assert_eq!(texts(&db), vec!["first take", "second take", "no recording"]);
+
+        let recordings = db.get_recordings().unwrap();
+        assert_eq!(recordings.len(), 1);
+        assert_eq!(db.get_recording_file(recordings[0].id).unwrap().as_deref(), Some("a.webm"));
+        let linked: Vec<Option<i64>> = (1..=3)
+            .map(|id| db.get_transcription(id).unwrap().unwrap().recording_id)
+            .collect();
+        assert_eq!(linked, vec![Some(recordings[0].id), Some(recordings[0].id), None]);
+
+        // Rows from before the index existed are searchable
+        assert_eq!(indexed_ids(&db.conn, "transcriptions_fts"), vec![1, 2, 3]);
+        assert_eq!(matching_ids(&db.conn, "transcriptions_fts", "take"), vec![1, 2]);
+
+        let backup = Connection::open(dir.path().join("visper.v0.bak")).unwrap();
+        assert!(has_column(&backup.unchecked_transaction().unwrap(), "transcriptions", "audio_file").unwrap());
+    }
+
+    #[test]
+    fn new_database_needs_no_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        assert_eq!(user_version(&db.conn), MIGRATIONS.len());
+        assert!(db.save_transcription(&entry("hello")).is_ok());
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".bak"))
+            .collect();
+        assert!(files.is_empty(), "{:?}", files);
+    }
+
+    #[test]
+    fn refuses_a_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("visper.db");
+        Connection::open(&path).unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+
+        let err = DatabaseService::open(&path).err().unwrap().to_string();
+        assert!(err.contains("newer version of Visper"), "{}", err);
+    }
+
+    #[test]
+    fn continued_dictations_are_merged_into_the_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let recording_id = db.add_recording("0123.webm.gz").unwrap();
+        let id = db.save_transcription(&NewTranscription {
+            raw_text: Some("um we met Anna"),
+            recording_id: Some(recording_id),
+            ..entry("We met Anna.")
+        }).unwrap();
+
+        assert!(db.append_transcription(id, "Then we left.", Some("then uh we left"), 2.5).unwrap());
+        let merged = db.get_transcription(id).unwrap().unwrap();
+        assert_eq!(merged.text, "We met Anna. Then we left.");
+        assert_eq!(merged.raw_text.as_deref(), Some("um we met Anna then uh we left"));
+        assert_eq!(merged.duration_seconds, Some(3.5));
+        // The recording only covers the first part
+        assert_eq!(merged.recording_id, None);
+
+        // Without a verbatim transcript the cleaned text stands in for it
+        assert!(db.append_transcription(id, "Bye.", None, 1.0).unwrap());
+        let merged = db.get_transcription(id).unwrap().unwrap();
+        assert_eq!(merged.raw_text.as_deref(), Some("um we met Anna then uh we left Bye."));
+
+        let revisions: Vec<String> = db.get_revisions(id).unwrap().into_iter().map(|r| r.text).collect();
+        assert!(revisions.contains(&"We met Anna.".to_string()), "{:?}", revisions);
+        assert!(revisions.contains(&"We met Anna. Then we left.".to_string()), "{:?}", revisions);
+        assert!(!db.append_transcription(id + 1, "Missing.", None, 1.0).unwrap());
+    }
+
+    #[test]
+    fn trashed_entries_cannot_be_edited() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let id = db.save_transcription(&entry("draft")).unwrap();
+        assert!(db.update_transcription(id, "edited", revision_source::EDIT).unwrap());
+        assert!(db.delete_transcription(id).unwrap());
+
+        assert!(!db.update_transcription(id, "edited again", revision_source::EDIT).unwrap());
+        assert!(!db.append_transcription(id, "more", None, 1.0).unwrap());
+        assert!(!db.update_transcription(id + 1, "missing", revision_source::EDIT).unwrap());
+
+        // Neither the text nor the revisions changed while it was in the trash
+        assert_eq!(db.restore_transcriptions(&[id]).unwrap(), 1);
+        assert_eq!(db.get_transcription(id).unwrap().unwrap().text, "edited");
+        let revisions: Vec<String> = db.get_revisions(id).unwrap().into_iter().map(|r| r.text).collect();
+        assert_eq!(revisions, vec!["draft"]);
+    }
+
+    #[test]
+    fn trashed_entries_cannot_be_tagged_moved_or_favorited() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let id = db.save_transcription(&entry("draft")).unwrap();
+        let tag_id = db.add_tag(id, "work").unwrap();
+        let folder_id = db.create_folder("Notes").unwrap();
+        db.delete_transcription(id).unwrap();
+
+        assert!(db.add_tag(id, "later").is_err());
+        assert!(!db.remove_tag(id, tag_id).unwrap());
+        assert!(!db.set_folder(id, Some(folder_id)).unwrap());
+        assert!(db.toggle_favorite(id).is_err());
+        assert!(db.toggle_favorite(id + 1).is_err());
+        // A failed tagging doesn't leave its tag behind
+        assert_eq!(db.get_tags().unwrap().iter().map(|tag| tag.name.as_str()).collect::<Vec<_>>(), vec!["work"]);
+
+        db.restore_transcriptions(&[id]).unwrap();
+        let restored = db.get_transcription(id).unwrap().unwrap();
+        assert_eq!(restored.is_favorite, 0);
+        assert_eq!(restored.folder_id, None);
+        assert_eq!(db.get_tags().unwrap()[0].count, 1);
+        assert!(db.toggle_favorite(id).unwrap());
+    }
+
+    #[test]
+    fn restoring_a_revision_of_a_trashed_entry_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let id = db.save_transcription(&entry("draft")).unwrap();
+        db.update_transcription(id, "edited", revision_source::EDIT).unwrap();
+        let revision = db.get_revisions(id).unwrap()[0].id;
+        db.delete_transcription(id).unwrap();
+
+        assert!(!db.restore_revision(revision).unwrap());
+        db.restore_transcriptions(&[id]).unwrap();
+        assert!(db.restore_revision(revision).unwrap());
+        assert_eq!(db.get_transcription(id).unwrap().unwrap().text, "draft");
+    }
+
+    fn tag_counts(db: &DatabaseService) -> Vec<(String, i64)> {
+        db.get_tags().unwrap().into_iter().map(|tag| (tag.name, tag.count)).collect()
+    }
+
+    #[test]
+    fn tags_are_added_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let first = db.save_transcription(&entry("Meeting notes")).unwrap();
+        let second = db.save_transcription(&entry("Call the dentist")).unwrap();
+
+        let work = db.add_tag(first, "work").unwrap();
+        // Names match case-insensitively and ignore surrounding space
+        assert_eq!(db.add_tag(second, "  Work ").unwrap(), work);
+        assert_eq!(db.add_tag(first, "WORK").unwrap(), work);
+        let urgent = db.add_tag(first, "urgent").unwrap();
+        assert!(db.add_tag(first, "   ").is_err());
+        assert!(db.add_tag(second + 1, "work").is_err());
+        assert_eq!(tag_counts(&db), vec![("urgent".to_string(), 1), ("work".to_string(), 2)]);
+        assert_eq!(db.get_transcription(first).unwrap().unwrap().tags, vec!["urgent", "work"]);
+
+        assert!(db.remove_tag(first, work).unwrap());
+        assert!(!db.remove_tag(first, work).unwrap());
+        assert_eq!(tag_counts(&db), vec![("urgent".to_string(), 1), ("work".to_string(), 1)]);
+
+        // An unused tag stays until deleted, and deleting one untags its entries
+        assert!(db.remove_tag(first, urgent).unwrap());
+        assert_eq!(tag_counts(&db), vec![("urgent".to_string(), 0), ("work".to_string(), 1)]);
+        assert!(db.delete_tag(work).unwrap());
+        assert!(db.get_transcription(second).unwrap().unwrap().tags.is_empty());
+        assert_eq!(tag_counts(&db), vec![("urgent".to_string(), 0)]);
+    }
+
+    #[test]
+    fn tag_counts_leave_out_deleted_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids: Vec<i64> = (0..3).map(|_| db.save_transcription(&entry("Meeting notes")).unwrap()).collect();
+        for id in &ids {
+            db.add_tag(*id, "work").unwrap();
+        }
+        db.add_tag(ids[0], "draft").unwrap();
+
+        db.delete_transcription(ids[0]).unwrap();
+        assert_eq!(tag_counts(&db), vec![("draft".to_string(), 0), ("work".to_string(), 2)]);
+        let facets = db.query_transcriptions(&HistoryQuery::default()).unwrap().tag_counts;
+        assert_eq!(facets.iter().map(|tag| (tag.name.as_str(), tag.count)).collect::<Vec<_>>(), vec![("work", 2)]);
+
+        // Purging removes the tagging for good
+        db.empty_trash().unwrap();
+        let tagged: i64 = db.conn.query_row("SELECT COUNT(*) FROM transcription_tags", [], |row| row.get(0)).unwrap();
+        assert_eq!(tagged, 2);
+        assert_eq!(tag_counts(&db), vec![("draft".to_string(), 0), ("work".to_string(), 2)]);
+    }
+
+    #[test]
+    fn entries_move_between_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let id = db.save_transcription(&entry("Meeting notes")).unwrap();
+        let notes = db.create_folder("Notes").unwrap();
+        assert_eq!(db.create_folder("notes").unwrap(), notes);
+
+        assert!(db.set_folder(id, Some(notes)).unwrap());
+        assert_eq!(db.get_transcription(id).unwrap().unwrap().folder_id, Some(notes));
+        assert_eq!(db.get_folders().unwrap()[0].count, 1);
+
+        // A missing folder is refused and the entry stays put
+        let error = db.set_folder(id, Some(notes + 1)).unwrap_err().to_string();
+        assert!(error.contains("not found"), "{}", error);
+        assert_eq!(db.get_transcription(id).unwrap().unwrap().folder_id, Some(notes));
+        assert!(!db.set_folder(id + 1, Some(notes)).unwrap());
+
+        // Deleting the folder keeps its entries, outside any folder
+        assert!(db.delete_folder(notes).unwrap());
+        assert_eq!(db.get_transcription(id).unwrap().unwrap().folder_id, None);
+        assert!(db.set_folder(id, None).unwrap());
+    }
+
+    #[test]
+    fn retranscriptions_link_to_the_original_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let recording_id = db.add_recording("0a1b.webm.gz").unwrap();
+        assert_eq!(db.add_recording("0a1b.webm.gz").unwrap(), recording_id);
+        let id = db.save_transcription(&NewTranscription {
+            duration_seconds: 12.5,
+            mode: Some("clean"),
+            recording_id: Some(recording_id),
+            ..entry("Meeting notes")
+        }).unwrap();
+        let original = db.get_transcription(id).unwrap().unwrap();
+
+        let first = db.save_retranscription(&original, "Meeting notes.", Some("um meeting notes"), Some("en"), Some("whisper"), Some("clean")).unwrap();
+        let first = db.get_transcription(first).unwrap().unwrap();
+        assert_eq!(first.parent_id, Some(id));
+        assert_eq!(first.recording_id, Some(recording_id));
+        assert_eq!(first.duration_seconds, Some(12.5));
+        assert_eq!(first.raw_text.as_deref(), Some("um meeting notes"));
+        assert_eq!((first.language.as_deref(), first.provider.as_deref()), (Some("en"), Some("whisper")));
+
+        // Re-transcribing a re-transcription still links to the original
+        let second = db.save_retranscription(&first, "Réunion", None, Some("fr"), Some("gemini"), Some("translate")).unwrap();
+        let second = db.get_transcription(second).unwrap().unwrap();
+        assert_eq!(second.parent_id, Some(id));
+        assert_eq!(second.recording_id, Some(recording_id));
+        assert_eq!(second.mode.as_deref(), Some("translate"));
+        assert_eq!(db.get_recording_file(recording_id).unwrap().as_deref(), Some("0a1b.webm.gz"));
+
+        // All three share the recording, which outlives any one of them
+        let recording = db.get_recordings().unwrap().into_iter().find(|r| r.id == recording_id).unwrap();
+        assert!(recording.is_referenced);
+        assert!(!recording.is_favorite);
+    }
+
+    #[test]
+    fn favorites_toggle() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let id = db.save_transcription(&entry("Meeting notes")).unwrap();
+        assert!(db.toggle_favorite(id).unwrap());
+        assert!(!db.toggle_favorite(id).unwrap());
+        assert!(db.toggle_favorite(id + 1).is_err());
+    }
+
+    /// Ids of every match, following cursors two entries at a time
+    fn page_ids(db: &DatabaseService, query: &HistoryQuery) -> Vec<i64> {
+        let mut query = HistoryQuery { limit: 2, ..query.clone() };
+        let mut ids = Vec::new();
+        loop {
+            let page = db.query_transcriptions(&query).unwrap();
+            assert!(page.items.len() <= 2);
+            ids.extend(page.items.iter().map(|item| item.id));
+            assert!(ids.len() <= 100, "paging doesn't end");
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return ids,
+            }
+        }
+    }
+
+    fn query_ids(db: &DatabaseService, query: HistoryQuery) -> Vec<i64> {
+        let mut ids: Vec<i64> = db.query_transcriptions(&query).unwrap().items.iter().map(|item| item.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn filters_narrow_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let entries = [
+            ("2024-01-01 09:00:00", 2.0, Some("en"), Some("clean")),
+            ("2024-01-15 09:00:00", 30.5, Some("ar"), Some("clean")),
+            ("2024-02-01 09:00:00", 90.0, Some("en"), Some("translate")),
+            ("2024-02-01 10:00:00", 5.0, None, None),
+        ];
+        let ids: Vec<i64> = entries.iter()
+            .map(|(created_at, duration, language, mode)| {
+                let id = db.save_transcription(&NewTranscription {
+                    duration_seconds: *duration,
+                    language: *language,
+                    mode: *mode,
+                    ..entry("Meeting notes")
+                }).unwrap();
+                db.conn.execute("UPDATE transcriptions SET created_at = ?2 WHERE id = ?1", params![id, created_at]).unwrap();
+                id
+            })
+            .collect();
+        db.toggle_favorite(ids[1]).unwrap();
+        db.add_tag(ids[2], "work").unwrap();
+        let tag_id = db.add_tag(ids[3], "Work").unwrap();
+
+        let all = ids.clone();
+        assert_eq!(query_ids(&db, HistoryQuery::default()), all);
+        // `from` is inclusive and `to` exclusive; dates mean midnight
+        assert_eq!(
+            query_ids(&db, HistoryQuery { from: Some("2024-01-15 09:00:00".into()), to: Some("2024-02-01".into()), ..Default::default() }),
+            vec![ids[1]]
+        );
+        assert_eq!(query_ids(&db, HistoryQuery { from: Some("2024-02-01".into()), ..Default::default() }), vec![ids[2], ids[3]]);
+        assert!(db.query_transcriptions(&HistoryQuery { from: Some("yesterday-ish".into()), ..Default::default() }).is_err());
+        assert_eq!(query_ids(&db, HistoryQuery { favorites_only: true, ..Default::default() }), vec![ids[1]]);
+        // Duration bounds are inclusive
+        assert_eq!(query_ids(&db, HistoryQuery { min_duration: Some(5.0), ..Default::default() }), vec![ids[1], ids[2], ids[3]]);
+        assert_eq!(query_ids(&db, HistoryQuery { max_duration: Some(30.5), ..Default::default() }), vec![ids[0], ids[1], ids[3]]);
+        assert_eq!(
+            query_ids(&db, HistoryQuery { min_duration: Some(3.0), max_duration: Some(60.0), ..Default::default() }),
+            vec![ids[1], ids[3]]
+        );
+        assert_eq!(query_ids(&db, HistoryQuery { language: Some("en".into()), ..Default::default() }), vec![ids[0], ids[2]]);
+        assert_eq!(query_ids(&db, HistoryQuery { mode: Some("translate".into()), ..Default::default() }), vec![ids[2]]);
+        assert_eq!(query_ids(&db, HistoryQuery { tag_id: Some(tag_id), ..Default::default() }), vec![ids[2], ids[3]]);
+        // Filters combine
+        assert_eq!(
+            query_ids(&db, HistoryQuery { tag_id: Some(tag_id), language: Some("en".into()), ..Default::default() }),
+            vec![ids[2]]
+        );
+        assert!(query_ids(&db, HistoryQuery { favorites_only: true, mode: Some("translate".into()), ..Default::default() }).is_empty());
+
+        // The tag facet counts the matches, not the whole history
+        let result = db.query_transcriptions(&HistoryQuery { language: Some("en".into()), ..Default::default() }).unwrap();
+        assert_eq!(result.total, 2);
+        assert_eq!(result.tag_counts.iter().map(|tag| (tag.id, tag.count)).collect::<Vec<_>>(), vec![(tag_id, 1)]);
+    }
+
+    #[test]
+    fn pages_through_ties_in_every_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let mut expected: Vec<i64> = (0..25)
+            .map(|i| {
+                let text = format!("meeting{}", " notes".repeat(i % 3));
+                db.save_transcription(&NewTranscription {
+                    // Repeating fractions, so sort values only survive the cursor exactly
+                    duration_seconds: (i % 4) as f64 / 3.0 + 0.1,
+                    ..entry(&text)
+                }).unwrap()
+            })
+            .collect();
+        expected.sort();
+        // A handful of distinct seconds, so creation times tie too
+        db.conn.execute("UPDATE transcriptions SET created_at = datetime('2024-01-01', '+' || (id % 3) || ' seconds')", []).unwrap();
+
+        for sort in [SortKey::CreatedAt, SortKey::Duration, SortKey::Length, SortKey::Relevance] {
+            for direction in [SortDirection::Asc, SortDirection::Desc] {
+                for search in [None, Some("meeting")] {
+                    let query = HistoryQuery { search: search.map(String::from), sort, direction, ..Default::default() };
+                    let paged = page_ids(&db, &query);
+                    let whole: Vec<i64> = db.query_transcriptions(&HistoryQuery { limit: MAX_PAGE_SIZE, ..query.clone() })
+                        .unwrap().items.iter().map(|item| item.id).collect();
+                    assert_eq!(paged, whole, "{:?} {:?} {:?}", sort, direction, search);
+
+                    let mut seen = paged.clone();
+                    seen.sort();
+                    assert_eq!(seen, expected, "{:?} {:?} {:?}", sort, direction, search);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_must_match_the_query_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        for _ in 0..3 {
+            db.save_transcription(&entry("Meeting notes")).unwrap();
+        }
+        let query = HistoryQuery { limit: 1, sort: SortKey::Duration, ..Default::default() };
+        let cursor = db.query_transcriptions(&query).unwrap().next_cursor;
+        assert!(cursor.is_some());
+
+        let reversed = HistoryQuery { direction: SortDirection::Asc, cursor: cursor.clone(), ..query.clone() };
+        assert!(db.query_transcriptions(&reversed).is_err());
+        let resorted = HistoryQuery { sort: SortKey::Length, cursor, ..query.clone() };
+        assert!(db.query_transcriptions(&resorted).is_err());
+        let garbled = HistoryQuery { cursor: Some("not a cursor".into()), ..query };
+        assert!(db.query_transcriptions(&garbled).is_err());
+    }
+
+    fn search(db: &DatabaseService, search: &str, substring: bool) -> Vec<Transcription> {
+        let query = HistoryQuery {
+            search: Some(search.to_string()),
+            substring,
+            sort: SortKey::Relevance,
+            ..Default::default()
+        };
+        db.query_transcriptions(&query).unwrap().items
+    }
+
+    fn search_ids(db: &DatabaseService, query: &str, substring: bool) -> Vec<i64> {
+        let mut ids: Vec<i64> = search(db, query, substring).iter().map(|item| item.id).collect();
+        ids.sort();
+        ids
+    }
+
+    /// Mixed Arabic and English history, returning the ids in order
+    fn mixed_history(db: &DatabaseService) -> Vec<i64> {
+        [
+            "ذهبنا إلى المدرسةِ صباحًا",
+            "Meeting notes: اجتماع الميزانية يوم الأحد",
+            "قرأتُ الكتابَ مع أصدقائي في المكتبة",
+            "Budget review for 2024, مستشفى الأطفال",
+            "The bookshop was closed",
+        ]
+        .iter()
+        .map(|text| db.save_transcription(&entry(text)).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn arabic_search_ignores_diacritics_and_letter_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+
+        // Taa marbuta, hamza on alef and alef maqsura, each typed plainly
+        assert_eq!(search_ids(&db, "المدرسه", false), vec![ids[0]]);
+        assert_eq!(search_ids(&db, "الى", false), vec![ids[0]]);
+        assert_eq!(search_ids(&db, "مستشفي", false), vec![ids[3]]);
+        // Typed with diacritics the text doesn't have
+        assert_eq!(search_ids(&db, "اجتماعُ", false), vec![ids[1]]);
+        // Tatweel in the search
+        assert_eq!(search_ids(&db, "الكـتاب", false), vec![ids[2]]);
+        assert_eq!(search_ids(&db, "اصدقائي", false), vec![ids[2]]);
+    }
+
+    #[test]
+    fn word_search_handles_mixed_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+
+        assert_eq!(search_ids(&db, "meeting الميزانيه", false), vec![ids[1]]);
+        assert_eq!(search_ids(&db, "budget OR المكتبه", false), vec![ids[2], ids[3]]);
+        assert_eq!(search_ids(&db, "\"notes اجتماع\"", false), vec![ids[1]]);
+        assert_eq!(search_ids(&db, "book*", false), vec![ids[4]]);
+        assert_eq!(search_ids(&db, "2024", false), vec![ids[3]]);
+        assert_eq!(search_ids(&db, "budget -الاطفال", false), Vec::<i64>::new());
+        // Whole words only: the article is part of the Arabic word
+        assert_eq!(search_ids(&db, "كتاب", false), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn substring_search_matches_inside_words() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+
+        assert_eq!(search_ids(&db, "كتاب", true), vec![ids[2]]);
+        assert_eq!(search_ids(&db, "ميزان", true), vec![ids[1]]);
+        assert_eq!(search_ids(&db, "ook", true), vec![ids[4]]);
+        assert_eq!(search_ids(&db, "مدرسه", true), vec![ids[0]]);
+        assert_eq!(search_ids(&db, "ook -closed", true), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn snippets_highlight_the_original_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        mixed_history(&db);
+
+        // The hit is marked in the stored text, diacritics and all
+        let items = search(&db, "المدرسه", false);
+        let snippet = items[0].snippet.as_deref().unwrap();
+        assert!(snippet.contains("\u{2}المدرسةِ\u{3}"), "{:?}", snippet);
+        assert!(snippet.contains("صباحًا"), "{:?}", snippet);
+
+        let items = search(&db, "اصدقائي", false);
+        let snippet = items[0].snippet.as_deref().unwrap();
+        assert!(snippet.contains("\u{2}أصدقائي\u{3}"), "{:?}", snippet);
+
+        let items = search(&db, "meeting الميزانيه", false);
+        let snippet = items[0].snippet.as_deref().unwrap();
+        assert!(snippet.contains("\u{2}Meeting\u{3}"), "{:?}", snippet);
+        assert!(snippet.contains("\u{2}الميزانية\u{3}"), "{:?}", snippet);
+    }
+
+    fn fuzzy(db: &DatabaseService, search: &str) -> HistoryResult {
+        let query = HistoryQuery { search: Some(search.to_string()), fuzzy: true, ..Default::default() };
+        db.query_transcriptions(&query).unwrap()
+    }
+
+    #[test]
+    fn fuzzy_total_is_exact_below_the_candidate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+
+        let result = fuzzy(&db, "meting");
+        assert_eq!(result.items.iter().map(|item| item.id).collect::<Vec<_>>(), vec![ids[1]]);
+        assert_eq!(result.total, 1);
+        assert!(!result.total_is_lower_bound);
+    }
+
+    #[test]
+    fn fuzzy_total_is_a_lower_bound_past_the_candidate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.conn.execute_batch("BEGIN").unwrap();
+        for i in 0..FUZZY_CANDIDATES + 20 {
+            db.save_transcription(&entry(&format!("budget review {}", i))).unwrap();
+        }
+        db.conn.execute_batch("COMMIT").unwrap();
+
+        let result = fuzzy(&db, "budgte");
+        assert_eq!(result.total, FUZZY_CANDIDATES);
+        assert!(result.total_is_lower_bound);
+    }
+
+    #[test]
+    fn fuzzy_total_stays_a_lower_bound_when_filters_narrow_the_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.conn.execute_batch("BEGIN").unwrap();
+        let ids: Vec<i64> = (0..FUZZY_CANDIDATES + 20)
+            .map(|i| db.save_transcription(&entry(&format!("budget review {}", i))).unwrap())
+            .collect();
+        db.conn.execute_batch("COMMIT").unwrap();
+        for id in ids.iter().step_by(100) {
+            db.toggle_favorite(*id).unwrap();
+        }
+
+        let query = HistoryQuery {
+            search: Some("budgte".to_string()),
+            fuzzy: true,
+            favorites_only: true,
+            ..Default::default()
+        };
+        let result = db.query_transcriptions(&query).unwrap();
+        // Some favorites may be among the trigram hits the limit cut off
+        assert!(result.total < FUZZY_CANDIDATES);
+        assert!(result.items.iter().all(|item| item.is_favorite == 1));
+        assert!(result.total_is_lower_bound);
+
+        // A search with few hits is still exact under a filter
+        db.save_transcription(&entry("kubernetes rollout")).unwrap();
+        let query = HistoryQuery { search: Some("cubernetes".to_string()), ..query };
+        let result = db.query_transcriptions(&query).unwrap();
+        assert_eq!(result.total, 0);
+        assert!(!result.total_is_lower_bound);
+    }
+
+    #[test]
+    fn fuzzy_total_for_short_words_is_bounded_by_the_newest_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.conn.execute_batch("BEGIN").unwrap();
+        for i in 0..FUZZY_CANDIDATES + 20 {
+            db.save_transcription(&entry(&format!("ok {}", i))).unwrap();
+        }
+        db.conn.execute_batch("COMMIT").unwrap();
+
+        let result = fuzzy(&db, "ok");
+        assert_eq!(result.total, FUZZY_CANDIDATES);
+        assert!(result.total_is_lower_bound);
+    }
+
+    /// One backfill pass as the app runs it: embed what's pending, then store it
+    fn backfill(db: &DatabaseService, embedder: Embedder) -> usize {
+        let pending = db.get_unembedded(embedder.model(), embeddings::BACKFILL_BATCH).unwrap();
+        pending.iter()
+            .filter(|(id, text)| {
+                db.save_embedding(*id, text, embedder.model(), &embeddings::fake_embedding(text)).unwrap()
+            })
+            .count()
+    }
+
+    fn semantic_ids(db: &DatabaseService, query: &str, k: usize) -> Vec<i64> {
+        db.semantic_search(Embedder::Fake.model(), &embeddings::fake_embedding(query), k).unwrap()
+            .into_iter()
+            .map(|m| m.item.id)
+            .collect()
+    }
+
+    #[test]
+    fn backfill_marks_entries_as_embedded() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+        db.delete_transcription(ids[4]).unwrap();
+
+        let model = Embedder::Fake.model();
+        assert_eq!(db.get_unembedded(model, 2).unwrap().iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![ids[3], ids[2]]);
+        // Trashed entries wait until they're restored
+        assert_eq!(backfill(&db, Embedder::Fake), 4);
+        assert!(db.get_unembedded(model, 10).unwrap().is_empty());
+        assert_eq!(backfill(&db, Embedder::Fake), 0);
+
+        // Vectors are per model
+        assert_eq!(db.get_unembedded("other-model", 10).unwrap().len(), 4);
+
+        // An edit drops the stale vector, and the next pass embeds the new text
+        db.update_transcription(ids[1], "Agenda for the offsite", revision_source::EDIT).unwrap();
+        assert_eq!(db.get_unembedded(model, 10).unwrap(), vec![(ids[1], "Agenda for the offsite".to_string())]);
+        assert_eq!(backfill(&db, Embedder::Fake), 1);
+
+        db.restore_transcriptions(&[ids[4]]).unwrap();
+        assert_eq!(backfill(&db, Embedder::Fake), 1);
+        assert!(db.get_unembedded(model, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn embedding_of_changed_text_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let id = db.save_transcription(&entry("first draft")).unwrap();
+        let model = Embedder::Fake.model();
+
+        let pending = db.get_unembedded(model, 10).unwrap();
+        db.update_transcription(id, "second draft", revision_source::EDIT).unwrap();
+        let (_, text) = &pending[0];
+        assert!(!db.save_embedding(id, text, model, &embeddings::fake_embedding(text)).unwrap());
+        assert!(!db.save_embedding(id + 1, "missing", model, &[1.0]).unwrap());
+        assert_eq!(db.get_unembedded(model, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn semantic_search_ranks_by_similarity() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids: Vec<i64> = [
+            "budget meeting notes",
+            "call the dentist",
+            "budget review",
+            "meeting with the budget committee about the budget",
+        ]
+        .iter()
+        .map(|text| db.save_transcription(&entry(text)).unwrap())
+        .collect();
+        backfill(&db, Embedder::Fake);
+
+        assert_eq!(semantic_ids(&db, "budget meeting notes", 4)[0], ids[0]);
+        assert_eq!(semantic_ids(&db, "dentist", 1), vec![ids[1]]);
+        let budget = semantic_ids(&db, "budget", 4);
+        assert_eq!(budget.last(), Some(&ids[1]), "{:?}", budget);
+
+        let matches = db.semantic_search(Embedder::Fake.model(), &embeddings::fake_embedding("budget meeting notes"), 4).unwrap();
+        assert!((matches[0].similarity - 1.0).abs() < 1e-5);
+        assert!(matches.windows(2).all(|pair| pair[0].similarity >= pair[1].similarity));
+
+        // Trashed entries and other models' vectors are left out
+        db.delete_transcription(ids[0]).unwrap();
+        assert!(!semantic_ids(&db, "budget meeting notes", 4).contains(&ids[0]));
+        assert!(db.semantic_search("other-model", &embeddings::fake_embedding("budget"), 4).unwrap().is_empty());
+    }
+
+    /// Backdate when an entry was trashed
+    fn trashed_ago(db: &DatabaseService, id: i64, modifier: &str) {
+        db.conn.execute(
+            "UPDATE transcriptions SET deleted_at = datetime('now', ?2) WHERE id = ?1",
+            params![id, modifier],
+        ).unwrap();
+    }
+
+    #[test]
+    fn trash_restore_and_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+        let source = db.get_transcription(ids[1]).unwrap().unwrap();
+        let rewrite = db.save_rewrite(&source, "Short budget notes", "shorten", None, None).unwrap();
+
+        // Trashed entries leave history, search and the tag counts
+        assert!(db.delete_transcription(ids[1]).unwrap());
+        assert!(!db.delete_transcription(ids[1]).unwrap());
+        assert!(db.get_transcription(ids[1]).unwrap().is_none());
+        assert!(search_ids(&db, "meeting", false).is_empty());
+        assert!(search_ids(&db, "ميزان", true).is_empty());
+        let trash = db.get_trash().unwrap();
+        assert_eq!(trash.iter().map(|item| item.id).collect::<Vec<_>>(), vec![ids[1]]);
+        assert!(trash[0].deleted_at.is_some());
+
+        // Restoring brings the entry and its index entries back
+        assert_eq!(db.restore_transcriptions(&[ids[1], ids[0]]).unwrap(), 1);
+        assert_eq!(search_ids(&db, "meeting", false), vec![ids[1]]);
+        assert_eq!(search_ids(&db, "ميزان", true), vec![ids[1]]);
+        assert!(db.get_transcription(ids[1]).unwrap().unwrap().deleted_at.is_none());
+        assert!(db.get_trash().unwrap().is_empty());
+
+        // Clearing trashes everything and can be undone
+        let cleared = db.clear_history().unwrap();
+        assert_eq!(cleared.len(), ids.len() + 1);
+        assert_eq!(db.query_transcriptions(&HistoryQuery::default()).unwrap().total, 0);
+        assert_eq!(db.restore_transcriptions(&cleared).unwrap(), cleared.len());
+        assert_eq!(db.query_transcriptions(&HistoryQuery::default()).unwrap().total, cleared.len() as i64);
+
+        // Emptying the trash deletes for good; rewrites outlive their original
+        db.delete_transcription(ids[1]).unwrap();
+        assert_eq!(db.empty_trash().unwrap(), 1);
+        assert!(db.get_trash().unwrap().is_empty());
+        assert_eq!(db.restore_transcriptions(&[ids[1]]).unwrap(), 0);
+        assert_eq!(db.get_transcription(rewrite).unwrap().unwrap().parent_id, None);
+        assert!(search_ids(&db, "meeting", false).is_empty());
+        assert_eq!(search_ids(&db, "budget", false), vec![ids[3], rewrite]);
+    }
+
+    #[test]
+    fn purge_honors_the_retention_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let ids = mixed_history(&db);
+        db.delete_transcription(ids[0]).unwrap();
+        db.delete_transcription(ids[1]).unwrap();
+        db.delete_transcription(ids[2]).unwrap();
+        trashed_ago(&db, ids[0], "-31 days");
+        trashed_ago(&db, ids[1], "-30 days");
+        trashed_ago(&db, ids[2], "-30 days");
+        db.conn.execute(
+            "UPDATE transcriptions SET deleted_at = datetime(deleted_at, '+1 minute') WHERE id = ?1",
+            params![ids[2]],
+        ).unwrap();
+
+        // Exactly the retention period counts as expired; a minute short doesn't
+        assert_eq!(db.purge_trash(30).unwrap(), 2);
+        let trash: Vec<i64> = db.get_trash().unwrap().into_iter().map(|item| item.id).collect();
+        assert_eq!(trash, vec![ids[2]]);
+        assert_eq!(db.purge_trash(30).unwrap(), 0);
+
+        // Live entries are never purged, however old
+        db.conn.execute("UPDATE transcriptions SET created_at = datetime('now', '-400 days')", []).unwrap();
+        assert_eq!(db.purge_trash(1).unwrap(), 1);
+        assert!(db.get_trash().unwrap().is_empty());
+        assert_eq!(db.query_transcriptions(&HistoryQuery::default()).unwrap().total, 2);
 
-        Ok(is_favorite == 1)
+        // Just-trashed entries survive a one-day retention
+        db.delete_transcription(ids[3]).unwrap();
+        assert_eq!(db.purge_trash(1).unwrap(), 0);
+        assert_eq!(db.restore_transcriptions(&[ids[3]]).unwrap(), 1);
     }
 }
@@ -0,0 +1,137 @@
+// Vectors for searching history by meaning, and where they come from.
+
+use std::time::Duration;
+use crate::services::GeminiService;
+use crate::services::gemini::{self, EmbeddingTask};
+
+/// Entries embedded per request while backfilling
+pub const BACKFILL_BATCH: usize = 32;
+
+/// How long the backfill waits between passes when nothing wakes it sooner
+pub const BACKFILL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[cfg(test)]
+const FAKE_DIMENSIONS: usize = 256;
+
+/// Source of embeddings. Vectors are only compared with others from the same model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Embedder {
+    Gemini,
+    /// Hashes words into a fixed-size vector: deterministic and offline
+    #[cfg(test)]
+    Fake,
+}
+
+impl Embedder {
+    /// Stored with each vector
+    pub fn model(self) -> &'static str {
+        match self {
+            Embedder::Gemini => gemini::EMBEDDING_MODEL,
+            #[cfg(test)]
+            Embedder::Fake => "fake-hashed-words",
+        }
+    }
+
+    pub async fn embed(
+        self,
+        gemini: &GeminiService,
+        texts: &[String],
+        task: EmbeddingTask,
+    ) -> Result<Vec<Vec<f32>>, String> {
+        match self {
+            Embedder::Gemini => gemini.embed(texts, task).await,
+            #[cfg(test)]
+            Embedder::Fake => Ok(texts.iter().map(|text| fake_embedding(text)).collect()),
+        }
+    }
+}
+
+/// Bag of words hashed into `FAKE_DIMENSIONS` buckets, normalized to unit length.
+/// Texts sharing words come out similar, so search results are predictable.
+#[cfg(test)]
+pub fn fake_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0f32; FAKE_DIMENSIONS];
+    let text = crate::services::search_query::normalize(text).to_lowercase();
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        let hash = fnv1a(word.as_bytes());
+        let sign = if hash & 1 == 0 { 1.0 } else { -1.0 };
+        vector[(hash >> 1) as usize % FAKE_DIMENSIONS] += sign;
+    }
+    normalize(&mut vector);
+    vector
+}
+
+#[cfg(test)]
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Cosine similarity from -1.0 to 1.0; 0.0 if either vector is zero or they differ in length
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Little-endian `f32`s, as vectors are stored in SQLite
+pub fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_embeddings_are_unit_length_and_deterministic() {
+        let vector = fake_embedding("Budget meeting on Sunday");
+        assert_eq!(vector.len(), FAKE_DIMENSIONS);
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5, "{}", norm);
+        assert_eq!(vector, fake_embedding("budget MEETING, on sunday!"));
+        assert!(fake_embedding("?!").iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn shared_words_make_texts_similar() {
+        let query = fake_embedding("budget meeting");
+        let close = cosine_similarity(&query, &fake_embedding("notes from the budget meeting"));
+        let partial = cosine_similarity(&query, &fake_embedding("budget review"));
+        let unrelated = cosine_similarity(&query, &fake_embedding("call the dentist"));
+        assert!(close > partial && partial > unrelated, "{} {} {}", close, partial, unrelated);
+    }
+
+    #[test]
+    fn cosine_similarity_edge_cases() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn blobs_round_trip() {
+        let vector = vec![0.5, -1.25, f32::MIN_POSITIVE, 3.0];
+        let blob = to_blob(&vector);
+        assert_eq!(blob.len(), vector.len() * 4);
+        assert_eq!(from_blob(&blob), vector);
+    }
+}
@@ -0,0 +1,150 @@
+// Typo-tolerant matching for history search. Candidates come from the trigram
+// index; this ranks them by how closely each search word appears in the text,
+// ignoring spaces and punctuation so mis-split words still match.
+
+use crate::services::search_query;
+
+/// Search words, reduced to what the comparison looks at
+pub struct FuzzyQuery {
+    words: Vec<Vec<char>>,
+}
+
+impl FuzzyQuery {
+    pub fn new(query: &str) -> Self {
+        let words = search_query::normalize(query)
+            .split_whitespace()
+            .map(comparable)
+            .filter(|word| !word.is_empty())
+            .collect();
+        Self { words }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// FTS5 query for the trigram index matching any trigram of any word
+    pub fn candidates(&self) -> Option<String> {
+        let mut trigrams: Vec<String> = self.words.iter()
+            .flat_map(|word| word.windows(3).map(|w| w.iter().collect::<String>()))
+            .collect();
+        trigrams.sort();
+        trigrams.dedup();
+        if trigrams.is_empty() {
+            return None;
+        }
+        Some(trigrams.iter().map(|t| format!("\"{}\"", t)).collect::<Vec<_>>().join(" OR "))
+    }
+
+    /// 1.0 when every word appears exactly, falling with the edits needed.
+    /// `None` when some word is too far off to count as a match.
+    pub fn similarity(&self, text: &str) -> Option<f64> {
+        let text = comparable(&search_query::normalize(text));
+        let mut total = 0.0;
+        for word in &self.words {
+            let distance = substring_distance(word, &text);
+            // Roughly one edit per three characters; short words must match exactly
+            if distance * 10 > word.len() * 3 {
+                return None;
+            }
+            total += 1.0 - distance as f64 / word.len() as f64;
+        }
+        Some(total / self.words.len() as f64)
+    }
+}
+
+/// Lowercase letters and digits only
+fn comparable(text: &str) -> Vec<char> {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Fewest edits turning `pattern` into some substring of `text` (Sellers' algorithm)
+fn substring_distance(pattern: &[char], text: &[char]) -> usize {
+    // column[i]: edits for pattern[..i] to end at the current text position
+    let mut column: Vec<usize> = (0..=pattern.len()).collect();
+    let mut best = pattern.len();
+    for &c in text {
+        let mut diagonal = column[0];
+        for i in 1..=pattern.len() {
+            let cost = usize::from(pattern[i - 1] != c);
+            let value = (diagonal + cost).min(column[i] + 1).min(column[i - 1] + 1);
+            diagonal = column[i];
+            column[i] = value;
+        }
+        best = best.min(column[pattern.len()]);
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(pattern: &str, text: &str) -> usize {
+        substring_distance(&pattern.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn exact_substrings_cost_nothing() {
+        assert_eq!(distance("budget", "thebudgetreview"), 0);
+        assert_eq!(distance("budget", "budget"), 0);
+        assert_eq!(distance("", "anything"), 0);
+    }
+
+    #[test]
+    fn counts_single_edits() {
+        // Substitution, insertion and deletion
+        assert_eq!(distance("budget", "thebudjetreview"), 1);
+        assert_eq!(distance("budget", "thebuddgetreview"), 1);
+        assert_eq!(distance("budget", "thebudetreview"), 1);
+        // Transposition is two edits
+        assert_eq!(distance("budget", "thebugdetreview"), 2);
+    }
+
+    #[test]
+    fn is_bounded_by_the_pattern_length() {
+        assert_eq!(distance("budget", ""), 6);
+        assert_eq!(distance("abc", "xyz"), 3);
+        // A text shorter than the pattern still lines up with part of it
+        assert_eq!(distance("budget", "budg"), 2);
+    }
+
+    #[test]
+    fn handles_arabic() {
+        assert_eq!(distance("مدرسه", "ذهبناالىالمدرسهصباحا"), 0);
+        assert_eq!(distance("مدرسه", "ذهبناالىالمدرصهصباحا"), 1);
+    }
+
+    #[test]
+    fn similarity_ignores_spacing_and_case() {
+        let query = FuzzyQuery::new("Budget Review");
+        assert_eq!(query.similarity("the budgetreview for 2024"), Some(1.0));
+        assert_eq!(query.similarity("The BUDGET, review."), Some(1.0));
+        // Diacritics and letter variants are folded before comparing
+        assert_eq!(FuzzyQuery::new("المدرسه").similarity("إلى المدرسةِ"), Some(1.0));
+    }
+
+    #[test]
+    fn similarity_drops_with_edits_and_rejects_distant_text() {
+        let query = FuzzyQuery::new("meeting");
+        let similarity = query.similarity("the meting notes").unwrap();
+        assert!((similarity - (1.0 - 1.0 / 7.0)).abs() < 1e-9, "{}", similarity);
+        assert_eq!(query.similarity("call the dentist"), None);
+        // Short words must match exactly
+        let short = FuzzyQuery::new("cat");
+        assert_eq!(short.similarity("a cat"), Some(1.0));
+        assert_eq!(short.similarity("a cut"), None);
+        // Every word has to be close
+        assert_eq!(FuzzyQuery::new("meeting budget").similarity("meeting notes"), None);
+    }
+
+    #[test]
+    fn candidates_are_the_trigrams_of_every_word() {
+        assert_eq!(FuzzyQuery::new("abcd ab").candidates(), Some(r#""abc" OR "bcd""#.to_string()));
+        assert_eq!(FuzzyQuery::new("ab").candidates(), None);
+        assert!(FuzzyQuery::new("?!").is_empty());
+    }
+}
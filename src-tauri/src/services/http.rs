@@ -0,0 +1,208 @@
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use anyhow::Result;
+use std::time::Duration;
+use crate::services::SettingsService;
+use crate::services::settings::NetworkSettings;
+
+/// Hosts that never go through the proxy, so a local Whisper server stays reachable
+const NO_PROXY_HOSTS: &str = "localhost,127.0.0.1,::1";
+
+/// Build the HTTP client shared by all providers from the network settings.
+/// The default timeout covers requests without audio; audio requests set
+/// their own with `NetworkSettings::request_timeout`.
+pub fn build_client(network: &NetworkSettings, proxy_password: Option<&str>) -> Result<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(network.connect_timeout_seconds))
+        .timeout(Duration::from_secs(network.timeout_seconds));
+
+    if let Some(url) = &network.proxy_url {
+        let mut proxy = Proxy::all(url)
+            .map_err(|e| anyhow::anyhow!("Invalid proxy URL: {}", e))?
+            .no_proxy(NoProxy::from_string(NO_PROXY_HOSTS));
+        if let Some(username) = &network.proxy_username {
+            proxy = proxy.basic_auth(username, proxy_password.unwrap_or_default());
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(path) = &network.ca_bundle_path {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read CA bundle: {}", e))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| anyhow::anyhow!("Invalid CA bundle: {}", e))?;
+        if certificates.is_empty() {
+            anyhow::bail!("CA bundle contains no certificates: {}", path);
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Build a client for the given network settings with the proxy password from the keyring
+pub fn client_for(settings: &SettingsService, network: &NetworkSettings) -> Result<Client> {
+    let proxy_password = match settings.get_proxy_password() {
+        Ok(password) => password,
+        Err(e) => {
+            eprintln!("[Visper] Failed to read proxy password from keyring: {}", e);
+            None
+        }
+    };
+    build_client(network, proxy_password.as_deref())
+}
+
+/// Length of a PCM WAV recording, read from its header. `None` for other
+/// formats or a malformed header.
+pub fn audio_length(audio: &[u8], mime_type: &str) -> Option<Duration> {
+    if !mime_type.contains("wav") || audio.len() < 44 || &audio[0..4] != b"RIFF" || &audio[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let byte_rate = u32::from_le_bytes(audio[28..32].try_into().ok()?);
+    if byte_rate == 0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64((audio.len() - 44) as f64 / byte_rate as f64))
+}
+
+/// Parse a single-range `Range: bytes=...` header into inclusive offsets
+/// within `len`. `None` for anything else, which is served as a full response.
+pub fn byte_range(header: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = header.strip_prefix("bytes=")?.split_once('-')?;
+    let last = len.checked_sub(1)?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (len.saturating_sub(suffix.parse().ok()?), last),
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<usize>().ok()?.min(last)),
+    };
+    (start <= end).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::test_server::TestServer;
+
+    const TEST_CA: &str = include_str!("testdata/test_ca.pem");
+
+    #[test]
+    fn byte_range_reads_single_ranges() {
+        assert_eq!(byte_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(byte_range("bytes=0-0", 1000), Some((0, 0)));
+        assert_eq!(byte_range("bytes= 10 - 20 ", 1000), Some((10, 20)));
+        // Open-ended, and an end past the file clamped to it
+        assert_eq!(byte_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(byte_range("bytes=990-5000", 1000), Some((990, 999)));
+        // The last N bytes, or the whole file when N exceeds it
+        assert_eq!(byte_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(byte_range("bytes=-5000", 1000), Some((0, 999)));
+    }
+
+    #[test]
+    fn byte_range_rejects_unsatisfiable_and_malformed_ranges() {
+        // Starting at or past the end
+        assert_eq!(byte_range("bytes=1000-", 1000), None);
+        assert_eq!(byte_range("bytes=1000-1200", 1000), None);
+        assert_eq!(byte_range("bytes=-0", 1000), None);
+        assert_eq!(byte_range("bytes=0-", 0), None);
+        assert_eq!(byte_range("bytes=20-10", 1000), None);
+        for header in ["bytes=", "bytes=-", "bytes=abc-", "bytes=0-x", "bytes=--1", "items=0-99", "0-99", "bytes=0-1,5-6"] {
+            assert_eq!(byte_range(header, 1000), None, "{}", header);
+        }
+    }
+
+    /// PCM WAV header for `data_len` bytes at 16 kHz mono 16-bit, followed by silence
+    fn wav(data_len: u32, byte_rate: u32) -> Vec<u8> {
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&16_000u32.to_le_bytes());
+        wav.extend_from_slice(&byte_rate.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(44 + data_len as usize, 0);
+        wav
+    }
+
+    #[test]
+    fn audio_length_comes_from_the_wav_header() {
+        assert_eq!(audio_length(&wav(64_000, 32_000), "audio/wav"), Some(Duration::from_secs(2)));
+        assert_eq!(audio_length(&wav(16_000, 32_000), "audio/x-wav"), Some(Duration::from_millis(500)));
+        assert_eq!(audio_length(&wav(0, 32_000), "audio/wav"), Some(Duration::ZERO));
+
+        assert_eq!(audio_length(&wav(64_000, 32_000), "audio/webm"), None);
+        assert_eq!(audio_length(&wav(64_000, 0), "audio/wav"), None);
+        assert_eq!(audio_length(&wav(64_000, 32_000)[..43], "audio/wav"), None);
+        let mut not_riff = wav(64_000, 32_000);
+        not_riff[0..4].copy_from_slice(b"RIFX");
+        assert_eq!(audio_length(&not_riff, "audio/wav"), None);
+    }
+
+    fn network_with_ca(dir: &tempfile::TempDir, pem: &str) -> NetworkSettings {
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, pem).unwrap();
+        NetworkSettings { ca_bundle_path: Some(path.to_string_lossy().into_owned()), ..Default::default() }
+    }
+
+    fn error(network: &NetworkSettings) -> String {
+        build_client(network, None).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn ca_bundles_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(build_client(&network_with_ca(&dir, TEST_CA), None).is_ok());
+        // Certificates may sit among other PEM blocks and text
+        assert!(build_client(&network_with_ca(&dir, &format!("# corporate CA\n{}\n{}", TEST_CA, TEST_CA)), None).is_ok());
+
+        let corrupted = TEST_CA.replacen("MII", "M!I", 1);
+        assert!(error(&network_with_ca(&dir, &corrupted)).starts_with("Invalid CA bundle"));
+        assert!(error(&network_with_ca(&dir, "not a certificate")).starts_with("CA bundle contains no certificates"));
+        assert!(error(&network_with_ca(&dir, "")).starts_with("CA bundle contains no certificates"));
+
+        let missing = NetworkSettings {
+            ca_bundle_path: Some(dir.path().join("missing.pem").to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert!(error(&missing).starts_with("Failed to read CA bundle"));
+    }
+
+    #[test]
+    fn invalid_proxy_urls_are_rejected() {
+        let network = NetworkSettings { proxy_url: Some("not a url".to_string()), ..Default::default() };
+        assert!(error(&network).starts_with("Invalid proxy URL"));
+    }
+
+    #[tokio::test]
+    async fn requests_go_through_the_proxy_with_its_credentials() {
+        let proxy = TestServer::start(vec![(200, "{}".to_string())]);
+        let local = TestServer::start(vec![(200, "{}".to_string())]);
+        let network = NetworkSettings {
+            proxy_url: Some(proxy.url.clone()),
+            proxy_username: Some("alice".to_string()),
+            ..Default::default()
+        };
+        let client = build_client(&network, Some("s3cret")).unwrap();
+
+        let response = client.get("http://api.example.test/v1/models").send().await.unwrap();
+        assert!(response.status().is_success());
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].line.starts_with("GET http://api.example.test/v1/models "), "{}", requests[0].line);
+        // alice:s3cret
+        assert_eq!(requests[0].header("proxy-authorization"), Some("Basic YWxpY2U6czNjcmV0"));
+
+        // A local Whisper server is reached directly
+        client.get(format!("{}/inference", local.url)).send().await.unwrap();
+        assert_eq!(local.requests().len(), 1);
+        assert_eq!(proxy.requests().len(), 1);
+    }
+}
@@ -1,7 +1,23 @@
 pub mod database;
 pub mod settings;
 pub mod gemini;
+pub mod answer_guard;
+pub mod vertex;
+pub mod openai;
+pub mod whisper;
+pub mod provider_chain;
+pub mod http;
+pub mod text_diff;
+pub mod search_query;
+pub mod fuzzy;
+pub mod embeddings;
+pub mod ask;
+pub mod audio_store;
+#[cfg(test)]
+pub mod test_server;
 
 pub use database::DatabaseService;
 pub use settings::SettingsService;
 pub use gemini::GeminiService;
+pub use provider_chain::ProviderChain;
+pub use audio_store::AudioStore;
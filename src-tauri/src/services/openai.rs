@@ -0,0 +1,191 @@
+use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use std::time::Duration;
+use crate::services::settings::OpenAiSettings;
+
+#[derive(Debug, Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// Client for any endpoint implementing OpenAI's `/audio/transcriptions`
+pub struct OpenAiService {
+    client: Client,
+    api_key: Option<String>,
+    settings: OpenAiSettings,
+}
+
+impl OpenAiService {
+    pub fn new(client: Client, api_key: Option<String>, settings: OpenAiSettings) -> Self {
+        Self { client, api_key, settings }
+    }
+
+    /// Plain transcription of the recording. `context` is sent as the prompt,
+    /// which the model treats as preceding text. Errors are formatted for display.
+    pub async fn transcribe(
+        &self,
+        audio_buffer: &[u8],
+        mime_type: &str,
+        context: Option<&str>,
+        timeout: Duration,
+    ) -> Result<String, String> {
+        let file = Part::bytes(audio_buffer.to_vec())
+            .file_name(format!("recording.{}", file_extension(mime_type)))
+            .mime_str(mime_type)
+            .map_err(|e| format!("Invalid audio type: {}", e))?;
+        let mut form = Form::new()
+            .part("file", file)
+            .text("model", self.settings.model.clone())
+            .text("response_format", "json");
+        if let Some(context) = context {
+            form = form.text("prompt", context.to_string());
+        }
+
+        let url = format!("{}/audio/transcriptions", self.settings.base_url.trim_end_matches('/'));
+        let mut request = self.client.post(url).timeout(timeout).multipart(form);
+        // Self-hosted gateways often run without a key
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = request.send().await.map_err(|e| format!("Network error: {}", e))?;
+        let status = response.status();
+        let response_text = response.text().await.unwrap_or_default();
+
+        if !status.is_success() {
+            let message = serde_json::from_str::<ErrorResponse>(&response_text)
+                .map(|e| e.error.message)
+                .unwrap_or(response_text);
+            return Err(format!("OpenAI-compatible API error ({}): {}", status.as_u16(), message));
+        }
+
+        let transcription: TranscriptionResponse = serde_json::from_str(&response_text)
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+        Ok(transcription.text.trim().to_string())
+    }
+
+    pub fn update_api_key(&mut self, key: Option<String>) {
+        self.api_key = key;
+    }
+
+    pub fn update_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    pub fn update_settings(&mut self, settings: OpenAiSettings) {
+        self.settings = settings;
+    }
+}
+
+/// File extension the endpoint uses to detect the audio format
+pub fn file_extension(mime_type: &str) -> &'static str {
+    let base = mime_type.split(';').next().unwrap_or_default().trim();
+    match base {
+        "audio/webm" | "video/webm" => "webm",
+        "audio/ogg" => "ogg",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/m4a" | "audio/x-m4a" => "m4a",
+        "audio/flac" => "flac",
+        _ => "webm",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::test_server::TestServer;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn service(base_url: &str, api_key: Option<&str>) -> OpenAiService {
+        let client = Client::builder().no_proxy().build().unwrap();
+        let settings = OpenAiSettings { base_url: base_url.to_string(), model: "whisper-1".to_string() };
+        OpenAiService::new(client, api_key.map(String::from), settings)
+    }
+
+    #[tokio::test]
+    async fn sends_a_multipart_transcription_request() {
+        let server = TestServer::start(vec![(200, r#"{"text": "  Hello there. \n"}"#.to_string())]);
+        let openai = service(&format!("{}/v1/", server.url), Some("sk-test"));
+
+        let text = openai.transcribe(b"fake audio", "audio/ogg;codecs=opus", Some("Earlier text"), TIMEOUT).await;
+
+        assert_eq!(text.as_deref(), Ok("Hello there."));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert!(request.line.starts_with("POST /v1/audio/transcriptions "), "{}", request.line);
+        assert_eq!(request.header("authorization"), Some("Bearer sk-test"));
+        assert!(request.header("content-type").unwrap_or_default().starts_with("multipart/form-data; boundary="));
+        for expected in [
+            "name=\"file\"; filename=\"recording.ogg\"",
+            "Content-Type: audio/ogg;codecs=opus",
+            "fake audio",
+            "name=\"model\"\r\n\r\nwhisper-1\r\n",
+            "name=\"response_format\"\r\n\r\njson\r\n",
+            "name=\"prompt\"\r\n\r\nEarlier text\r\n",
+        ] {
+            assert!(request.body.contains(expected), "missing {:?} in {}", expected, request.body);
+        }
+    }
+
+    #[tokio::test]
+    async fn keyless_requests_have_no_authorization_or_prompt() {
+        let server = TestServer::start(vec![(200, r#"{"text": "Hi."}"#.to_string())]);
+        let openai = service(&server.url, None);
+
+        assert_eq!(openai.transcribe(b"fake audio", "audio/webm", None, TIMEOUT).await.as_deref(), Ok("Hi."));
+        let request = &server.requests()[0];
+        assert_eq!(request.header("authorization"), None);
+        assert!(!request.body.contains("name=\"prompt\""));
+        assert!(request.body.contains("filename=\"recording.webm\""));
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_status_and_message() {
+        let server = TestServer::start(vec![
+            (401, r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error"}}"#.to_string()),
+            (502, "Bad gateway".to_string()),
+            (200, r#"{"transcript": "wrong shape"}"#.to_string()),
+        ]);
+        let openai = service(&server.url, Some("sk-wrong"));
+        let transcribe = || openai.transcribe(b"fake audio", "audio/webm", None, TIMEOUT);
+
+        assert_eq!(transcribe().await, Err("OpenAI-compatible API error (401): Incorrect API key provided".to_string()));
+        // Bodies that aren't an OpenAI error are passed through as they are
+        assert_eq!(transcribe().await, Err("OpenAI-compatible API error (502): Bad gateway".to_string()));
+        let error = transcribe().await.unwrap_err();
+        assert!(error.starts_with("Failed to parse response:"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoint_is_a_network_error() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let openai = service(&format!("http://{}", address), None);
+
+        let error = openai.transcribe(b"fake audio", "audio/webm", None, TIMEOUT).await.unwrap_err();
+        assert!(error.starts_with("Network error:"), "{}", error);
+    }
+
+    #[test]
+    fn file_extension_follows_the_mime_type() {
+        assert_eq!(file_extension("audio/webm;codecs=opus"), "webm");
+        assert_eq!(file_extension("audio/x-wav"), "wav");
+        assert_eq!(file_extension("audio/mpeg"), "mp3");
+        assert_eq!(file_extension("audio/x-m4a"), "m4a");
+        assert_eq!(file_extension(" audio/flac "), "flac");
+        assert_eq!(file_extension("application/octet-stream"), "webm");
+    }
+}
@@ -0,0 +1,484 @@
+use reqwest::Client;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::services::gemini::{GeminiService, TestApiResult, TranscriptionRequest, TranscriptionResult};
+use crate::services::openai::OpenAiService;
+use crate::services::http;
+use crate::services::settings::{CircuitBreakerSettings, DictationMode, NetworkSettings, Provider, Settings};
+use crate::services::whisper::WhisperService;
+
+#[derive(Debug, Default)]
+struct ProviderHealth {
+    consecutive_failures: u32,
+    /// Set while the circuit is open; the provider is skipped until then
+    open_until: Option<Instant>,
+}
+
+/// Skips a provider for a cool-down period after repeated consecutive failures
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    health: HashMap<Provider, ProviderHealth>,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: CircuitBreakerSettings) -> Self {
+        Self { settings, health: HashMap::new() }
+    }
+
+    /// Whether the provider may be tried. Once the cool-down has passed it gets
+    /// one trial request; another failure opens the circuit again.
+    pub fn is_available(&self, provider: Provider) -> bool {
+        match self.health.get(&provider).and_then(|h| h.open_until) {
+            Some(open_until) => Instant::now() >= open_until,
+            None => true,
+        }
+    }
+
+    pub fn record_success(&mut self, provider: Provider) {
+        self.health.remove(&provider);
+    }
+
+    pub fn record_failure(&mut self, provider: Provider) {
+        let health = self.health.entry(provider).or_default();
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.settings.failure_threshold {
+            eprintln!(
+                "[Visper] {} failed {} times in a row. Skipping it for {}s.",
+                provider.label(), health.consecutive_failures, self.settings.cooldown_seconds
+            );
+            health.open_until = Some(Instant::now() + Duration::from_secs(self.settings.cooldown_seconds));
+        }
+    }
+
+    pub fn update_settings(&mut self, settings: CircuitBreakerSettings) {
+        self.settings = settings;
+    }
+}
+
+/// Runs a recording through the configured providers in order until one succeeds
+pub struct ProviderChain {
+    providers: Vec<Provider>,
+    breaker: CircuitBreaker,
+    openai: OpenAiService,
+    whisper: WhisperService,
+    network: NetworkSettings,
+}
+
+impl ProviderChain {
+    pub fn new(settings: &crate::services::SettingsService, client: Client) -> anyhow::Result<Self> {
+        let openai_key = match settings.get_openai_api_key() {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("[Visper] Failed to read OpenAI API key from keyring: {}", e);
+                None
+            }
+        };
+
+        let all = settings.get_all();
+        Ok(Self {
+            providers: all.providers,
+            breaker: CircuitBreaker::new(all.circuit_breaker),
+            openai: OpenAiService::new(client.clone(), openai_key, all.openai),
+            whisper: WhisperService::new(client, all.whisper),
+            network: all.network,
+        })
+    }
+
+    /// Transcribe with the first provider that succeeds. Providers that can't
+    /// honor the mode are left out. Providers whose circuit is open are skipped,
+    /// unless every provider is open, in which case all are tried anyway rather
+    /// than failing without a request.
+    pub async fn transcribe(
+        &mut self,
+        gemini: &GeminiService,
+        audio_buffer: &[u8],
+        mime_type: &str,
+        mode: DictationMode,
+        context: Option<&str>,
+    ) -> TranscriptionResult {
+        let supported: Vec<Provider> = self.providers.iter()
+            .copied()
+            .filter(|p| p.supports_mode(mode))
+            .collect();
+        if supported.is_empty() {
+            return TranscriptionResult::failure(unsupported_mode_message(mode));
+        }
+
+        let mut candidates: Vec<Provider> = supported.iter()
+            .copied()
+            .filter(|p| self.breaker.is_available(*p))
+            .collect();
+        if candidates.is_empty() {
+            candidates = supported;
+        }
+
+        let mut errors = Vec::new();
+        for provider in candidates {
+            let request = TranscriptionRequest { audio: audio_buffer, mime_type, provider, mode, context };
+            let mut result = self.transcribe_with(gemini, request).await;
+            if result.success {
+                self.breaker.record_success(provider);
+                result.provider = Some(provider);
+                return result;
+            }
+
+            let error = result.error.unwrap_or_default();
+            eprintln!("[Visper] {} failed: {}", provider.label(), error);
+            self.breaker.record_failure(provider);
+            errors.push((provider, error));
+        }
+
+        match errors.as_slice() {
+            [(_, error)] => TranscriptionResult::failure(error.clone()),
+            _ => TranscriptionResult::failure(format!(
+                "All providers failed. {}",
+                errors.iter()
+                    .map(|(p, e)| format!("{}: {}", p.label(), e))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            )),
+        }
+    }
+
+    /// Transcribe with one provider, bypassing the fallback order and circuit breaker
+    pub async fn transcribe_single(
+        &self,
+        gemini: &GeminiService,
+        provider: Provider,
+        audio_buffer: &[u8],
+        mime_type: &str,
+        mode: DictationMode,
+    ) -> TranscriptionResult {
+        let request = TranscriptionRequest { audio: audio_buffer, mime_type, provider, mode, context: None };
+        let mut result = self.transcribe_with(gemini, request).await;
+        if result.success {
+            result.provider = Some(provider);
+        }
+        result
+    }
+
+    /// Gemini and Vertex AI honor the dictation mode; the fallback providers
+    /// only produce a plain transcript, so they refuse any other mode.
+    async fn transcribe_with(&self, gemini: &GeminiService, req: TranscriptionRequest<'_>) -> TranscriptionResult {
+        if !req.provider.supports_mode(req.mode) {
+            return TranscriptionResult::failure(format!(
+                "{} only transcribes and can't be used in {} mode.",
+                req.provider.label(), req.mode.as_str()
+            ));
+        }
+        let timeout = self.network.request_timeout(http::audio_length(req.audio, req.mime_type));
+        let text = match req.provider {
+            Provider::Gemini | Provider::Vertex => {
+                return gemini.transcribe(&req).await
+                    .unwrap_or_else(|e| TranscriptionResult::failure(e.to_string()));
+            }
+            Provider::OpenAi => self.openai.transcribe(req.audio, req.mime_type, req.context, timeout).await,
+            Provider::Whisper => self.whisper.transcribe(req.audio, req.mime_type, req.context, timeout).await,
+        };
+
+        match text {
+            Ok(text) if text.is_empty() => TranscriptionResult::inaudible(),
+            Ok(text) => TranscriptionResult {
+                success: true,
+                text: Some(text),
+                ..Default::default()
+            },
+            Err(e) => TranscriptionResult::failure(e),
+        }
+    }
+
+    /// Check that a fallback provider is reachable by sending it a short silent clip
+    pub async fn test_connection(&self, provider: Provider) -> TestApiResult {
+        let audio = silent_wav();
+        let timeout = self.network.request_timeout(None);
+        let result = match provider {
+            Provider::OpenAi => self.openai.transcribe(&audio, "audio/wav", None, timeout).await,
+            Provider::Whisper => self.whisper.transcribe(&audio, "audio/wav", None, timeout).await,
+            other => Err(format!("{} is tested through the Gemini service", other.label())),
+        };
+
+        match result {
+            Ok(_) => TestApiResult { success: true, error: None },
+            Err(e) => TestApiResult { success: false, error: Some(e) },
+        }
+    }
+
+    pub fn update_openai_api_key(&mut self, key: Option<String>) {
+        self.openai.update_api_key(key);
+    }
+
+    /// Swap in a client rebuilt for new network settings
+    pub fn update_client(&mut self, client: Client) {
+        self.openai.update_client(client.clone());
+        self.whisper.update_client(client);
+    }
+
+    pub fn update_settings(&mut self, settings: Settings) {
+        self.providers = settings.providers;
+        self.network = settings.network;
+        self.breaker.update_settings(settings.circuit_breaker);
+        self.openai.update_settings(settings.openai);
+        self.whisper.update_settings(settings.whisper);
+    }
+}
+
+fn unsupported_mode_message(mode: DictationMode) -> String {
+    format!(
+        "No provider in the fallback order supports {} mode. Add Gemini or Vertex AI, or switch to clean mode.",
+        mode.as_str()
+    )
+}
+
+/// Half a second of 16 kHz mono 16-bit silence
+fn silent_wav() -> Vec<u8> {
+    const SAMPLE_RATE: u32 = 16_000;
+    let data_len = SAMPLE_RATE; // 0.5s * 2 bytes per sample
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.resize(44 + data_len as usize, 0);
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::settings::{OpenAiSettings, WhisperSettings};
+    use crate::services::test_server::TestServer;
+
+    const AUDIO: &[u8] = b"not really audio";
+
+    /// generateContent response carrying a structured transcript
+    fn gemini_reply(text: &str) -> (u16, String) {
+        let transcript = serde_json::json!({
+            "text": text,
+            "language": "fr",
+            "confidence": 0.9,
+            "is_inaudible": false,
+            "is_transcription": true,
+            "verbatim": text,
+        });
+        let body = serde_json::json!({ "candidates": [{ "content": { "parts": [{ "text": transcript.to_string() }] } }] });
+        (200, body.to_string())
+    }
+
+    fn openai_reply(text: &str) -> (u16, String) {
+        (200, serde_json::json!({ "text": text }).to_string())
+    }
+
+    fn chain(providers: Vec<Provider>, openai: &TestServer, whisper: &TestServer) -> ProviderChain {
+        let client = Client::builder().no_proxy().build().unwrap();
+        let settings = Settings::default();
+        ProviderChain {
+            providers,
+            breaker: CircuitBreaker::new(settings.circuit_breaker),
+            openai: OpenAiService::new(
+                client.clone(),
+                None,
+                OpenAiSettings { base_url: openai.url.clone(), ..Default::default() },
+            ),
+            whisper: WhisperService::new(client, WhisperSettings { url: format!("{}/inference", whisper.url) }),
+            network: settings.network,
+        }
+    }
+
+    fn openai_error(status: u16) -> (u16, String) {
+        (status, r#"{"error":{"message":"unavailable"}}"#.to_string())
+    }
+
+    /// OpenAI first, local Whisper as the fallback, tripping after two failures
+    fn breaker_chain(openai: &TestServer, whisper: &TestServer, cooldown_seconds: u64) -> ProviderChain {
+        let mut chain = chain(vec![Provider::OpenAi, Provider::Whisper], openai, whisper);
+        chain.breaker = CircuitBreaker::new(CircuitBreakerSettings { failure_threshold: 2, cooldown_seconds });
+        chain
+    }
+
+    async fn clean(chain: &mut ProviderChain) -> TranscriptionResult {
+        let gemini = GeminiService::for_tests(Settings::default(), None);
+        chain.transcribe(&gemini, AUDIO, "audio/webm", DictationMode::Clean, None).await
+    }
+
+    fn gemini(server: &TestServer) -> GeminiService {
+        let mut settings = Settings::default();
+        settings.gemini.base_url = server.url.clone();
+        settings.target_language = "fr".to_string();
+        GeminiService::for_tests(settings, Some("test-key"))
+    }
+
+    #[tokio::test]
+    async fn translate_skips_providers_that_only_transcribe() {
+        let openai = TestServer::start(vec![openai_reply("Hello there.")]);
+        let whisper = TestServer::start(vec![]);
+        let gemini_server = TestServer::start(vec![gemini_reply("Bonjour.")]);
+        let mut chain = chain(vec![Provider::OpenAi, Provider::Gemini], &openai, &whisper);
+
+        let result = chain.transcribe(&gemini(&gemini_server), AUDIO, "audio/webm", DictationMode::Translate, None).await;
+
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.text.as_deref(), Some("Bonjour."));
+        assert_eq!(result.provider, Some(Provider::Gemini));
+        assert!(openai.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn translate_fails_when_no_provider_can_translate() {
+        let openai = TestServer::start(vec![openai_reply("Hello there.")]);
+        let whisper = TestServer::start(vec![]);
+        let gemini_server = TestServer::start(vec![]);
+        let mut chain = chain(vec![Provider::OpenAi, Provider::Whisper], &openai, &whisper);
+
+        let result = chain.transcribe(&gemini(&gemini_server), AUDIO, "audio/webm", DictationMode::Translate, None).await;
+
+        assert!(!result.success);
+        assert!(result.text.is_none());
+        let error = result.error.unwrap_or_default();
+        assert!(error.contains("supports translate mode"), "{}", error);
+        assert!(openai.requests().is_empty());
+        assert!(whisper.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn single_provider_refuses_a_mode_it_cannot_honor() {
+        let openai = TestServer::start(vec![openai_reply("Hello there.")]);
+        let whisper = TestServer::start(vec![]);
+        let gemini_server = TestServer::start(vec![]);
+        let chain = chain(vec![Provider::Gemini], &openai, &whisper);
+
+        let result = chain.transcribe_single(
+            &gemini(&gemini_server), Provider::OpenAi, AUDIO, "audio/webm", DictationMode::Translate,
+        ).await;
+
+        assert!(!result.success);
+        let error = result.error.unwrap_or_default();
+        assert!(error.contains("can't be used in translate mode"), "{}", error);
+        assert!(openai.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn clean_mode_falls_back_to_a_plain_transcript() {
+        let openai = TestServer::start(vec![openai_reply("Hello there.")]);
+        let whisper = TestServer::start(vec![]);
+        let gemini_server = TestServer::start(vec![(500, r#"{"error":{"message":"down"}}"#.to_string())]);
+        let mut chain = chain(vec![Provider::Gemini, Provider::OpenAi], &openai, &whisper);
+
+        let result = chain.transcribe(&gemini(&gemini_server), AUDIO, "audio/webm", DictationMode::Clean, None).await;
+
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.text.as_deref(), Some("Hello there."));
+        assert_eq!(result.provider, Some(Provider::OpenAi));
+        assert_eq!(openai.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn circuit_opens_after_the_failure_threshold() {
+        let openai = TestServer::start(vec![openai_error(503), openai_error(503)]);
+        let whisper = TestServer::start(vec![
+            openai_reply("One."), openai_reply("Two."), openai_reply("Three."),
+        ]);
+        let mut chain = breaker_chain(&openai, &whisper, 3600);
+
+        for expected in ["One.", "Two."] {
+            let result = clean(&mut chain).await;
+            assert_eq!(result.text.as_deref(), Some(expected));
+            assert_eq!(result.provider, Some(Provider::Whisper));
+        }
+        assert_eq!(openai.requests().len(), 2);
+        assert!(!chain.breaker.is_available(Provider::OpenAi));
+
+        // Skipped during the cool-down
+        let result = clean(&mut chain).await;
+        assert_eq!(result.text.as_deref(), Some("Three."));
+        assert_eq!(result.provider, Some(Provider::Whisper));
+        assert_eq!(openai.requests().len(), 2);
+        assert_eq!(whisper.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn success_resets_the_failure_count() {
+        let openai = TestServer::start(vec![
+            openai_error(503), openai_reply("Back."), openai_error(503), openai_reply("Still here."),
+        ]);
+        let whisper = TestServer::start(vec![openai_reply("Fallback one."), openai_reply("Fallback two.")]);
+        let mut chain = breaker_chain(&openai, &whisper, 3600);
+
+        let providers = [Provider::Whisper, Provider::OpenAi, Provider::Whisper, Provider::OpenAi];
+        for provider in providers {
+            let result = clean(&mut chain).await;
+            assert!(result.success, "{:?}", result.error);
+            assert_eq!(result.provider, Some(provider));
+        }
+        // Two failures, but never two in a row
+        assert_eq!(openai.requests().len(), 4);
+        assert!(chain.breaker.is_available(Provider::OpenAi));
+    }
+
+    #[tokio::test]
+    async fn trial_request_after_the_cooldown() {
+        let openai = TestServer::start(vec![
+            openai_error(503), openai_error(503), openai_error(503), openai_reply("Recovered."),
+        ]);
+        let whisper = TestServer::start(vec![
+            openai_reply("One."), openai_reply("Two."), openai_reply("Three."), openai_reply("Four."),
+        ]);
+        let mut chain = breaker_chain(&openai, &whisper, 1);
+
+        clean(&mut chain).await;
+        clean(&mut chain).await;
+        assert!(!chain.breaker.is_available(Provider::OpenAi));
+        clean(&mut chain).await;
+        assert_eq!(openai.requests().len(), 2);
+
+        // A failed trial opens the circuit again straight away
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(chain.breaker.is_available(Provider::OpenAi));
+        let result = clean(&mut chain).await;
+        assert_eq!(result.provider, Some(Provider::Whisper));
+        assert_eq!(openai.requests().len(), 3);
+        assert!(!chain.breaker.is_available(Provider::OpenAi));
+
+        // A successful one closes it
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let result = clean(&mut chain).await;
+        assert_eq!(result.text.as_deref(), Some("Recovered."));
+        assert_eq!(result.provider, Some(Provider::OpenAi));
+        assert!(chain.breaker.health.is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_provider_is_tried_when_all_circuits_are_open() {
+        let openai = TestServer::start(vec![]);
+        let whisper = TestServer::start(vec![
+            openai_error(500), openai_error(500), openai_reply("Finally."),
+        ]);
+        let mut chain = breaker_chain(&openai, &whisper, 3600);
+
+        for _ in 0..2 {
+            let result = clean(&mut chain).await;
+            assert!(!result.success);
+            assert!(result.error.unwrap_or_default().starts_with("All providers failed."));
+        }
+        assert!(!chain.breaker.is_available(Provider::OpenAi));
+        assert!(!chain.breaker.is_available(Provider::Whisper));
+
+        // Rather than failing without a request, both get tried in order
+        let result = clean(&mut chain).await;
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.text.as_deref(), Some("Finally."));
+        assert_eq!(result.provider, Some(Provider::Whisper));
+        assert_eq!(openai.requests().len(), 3);
+        assert_eq!(whisper.requests().len(), 3);
+        assert!(chain.breaker.is_available(Provider::Whisper));
+    }
+}
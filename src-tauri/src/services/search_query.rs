@@ -0,0 +1,299 @@
+// Turns what the user types into the history search box into FTS5 queries.
+//
+// Words and "quoted phrases" must all match; a trailing `*` matches by prefix.
+// `OR` between two items accepts either, binding tighter than the implicit AND,
+// so `cat OR dog food` finds food with either animal. `-item` or `NOT item`
+// leaves out entries matching the item.
+//
+// Text is normalized the same way when it's indexed and when it's searched,
+// so Arabic matches regardless of diacritics and letter variants.
+
+use rusqlite::Connection;
+use rusqlite::functions::FunctionFlags;
+
+/// SQL function the full-text index triggers feed text through
+pub const NORMALIZE_FUNCTION: &str = "normalize_search";
+
+/// Search split into FTS5 expressions. Entries must match `matching` and must
+/// not match `excluding`; `None` means no constraint.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    pub matching: Option<String>,
+    pub excluding: Option<String>,
+}
+
+enum Token {
+    Item { text: String, prefix: bool, negated: bool },
+    Or,
+    Not,
+}
+
+/// Fold spelling variants so a word matches however it was written: Arabic
+/// diacritics and tatweel are dropped; alef forms, hamza seats, alef maqsura
+/// and taa marbuta become their plain letters. Only letters inside words
+/// change, so the tokens still line up with the original text for snippets.
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\u{064B}'..='\u{065F}' | '\u{0670}' | '\u{06D6}'..='\u{06ED}' | '\u{0640}' => None,
+            'أ' | 'إ' | 'آ' | 'ٱ' => Some('ا'),
+            'ؤ' => Some('و'),
+            'ئ' | 'ى' => Some('ي'),
+            'ة' => Some('ه'),
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// Make `normalize` available to SQL as `NORMALIZE_FUNCTION`; needed on every
+/// connection before the history tables are written to
+pub fn register(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        NORMALIZE_FUNCTION,
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|text| normalize(&text))),
+    )
+}
+
+/// Parse a search for the word index, or with `substring` for the trigram
+/// index, where every item already matches inside words
+pub fn parse(input: &str, substring: bool) -> SearchQuery {
+    let input = normalize(input);
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut excluded: Vec<String> = Vec::new();
+    let mut or_pending = false;
+    let mut not_pending = false;
+    // OR only joins an item to the positive item right before it
+    let mut joinable = false;
+
+    for token in tokenize(&input) {
+        match token {
+            Token::Or => or_pending = true,
+            Token::Not => not_pending = true,
+            Token::Item { text, prefix, negated } => {
+                let negated = negated || std::mem::take(&mut not_pending);
+                let join = std::mem::take(&mut or_pending) && joinable;
+                let Some(item) = fts_item(&text, prefix && !substring) else {
+                    continue;
+                };
+                if negated {
+                    excluded.push(item);
+                    joinable = false;
+                    continue;
+                }
+                match groups.last_mut() {
+                    Some(group) if join => group.push(item),
+                    _ => groups.push(vec![item]),
+                }
+                joinable = true;
+            }
+        }
+    }
+
+    let excluding = (!excluded.is_empty()).then(|| excluded.join(" OR "));
+    if groups.is_empty() {
+        return SearchQuery { matching: None, excluding };
+    }
+
+    let matching = groups.iter()
+        .map(|group| match group.as_slice() {
+            [item] => item.clone(),
+            items => format!("({})", items.join(" OR ")),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    // With something to match, exclusions fold into the same expression
+    match excluding {
+        Some(excluding) => SearchQuery {
+            matching: Some(format!("({}) NOT ({})", matching, excluding)),
+            excluding: None,
+        },
+        None => SearchQuery { matching: Some(matching), excluding: None },
+    }
+}
+
+/// Query matching entries with any word of `input`, for finding entries related
+/// to a question rather than ones containing all of it. Best ranked by bm25.
+pub fn any_word(input: &str) -> Option<String> {
+    let input = normalize(input).to_lowercase();
+    let mut words: Vec<&str> = input.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    let items: Vec<String> = words.iter().filter_map(|word| fts_item(word, false)).collect();
+    (!items.is_empty()).then(|| items.join(" OR "))
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let negated = chars.next_if_eq(&'-').is_some();
+
+        if chars.next_if_eq(&'"').is_some() {
+            // An unclosed quote runs to the end of the input
+            let text: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            let prefix = chars.next_if_eq(&'*').is_some();
+            tokens.push(Token::Item { text, prefix, negated });
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != '"') {
+            word.push(c);
+        }
+        match word.as_str() {
+            "OR" if !negated => tokens.push(Token::Or),
+            "NOT" if !negated => tokens.push(Token::Not),
+            "AND" if !negated => {}
+            _ => {
+                let prefix = word.ends_with('*');
+                let text = word.trim_end_matches('*').to_string();
+                tokens.push(Token::Item { text, prefix, negated });
+            }
+        }
+    }
+
+    tokens
+}
+
+/// A quoted FTS5 string, so punctuation and keywords in it are plain text.
+/// `None` for items with nothing the tokenizer would index.
+fn fts_item(text: &str, prefix: bool) -> Option<String> {
+    if !text.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    Some(format!("\"{}\"{}", text.replace('"', "\"\""), if prefix { "*" } else { "" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matching(input: &str) -> Option<String> {
+        parse(input, false).matching
+    }
+
+    #[test]
+    fn folds_alef_and_hamza_forms() {
+        assert_eq!(normalize("أحمد إبراهيم آمن ٱلله"), "احمد ابراهيم امن الله");
+        assert_eq!(normalize("مؤمن"), "مومن");
+        assert_eq!(normalize("سائل"), "سايل");
+        // A bare hamza has no seat to fold into
+        assert_eq!(normalize("سماء"), "سماء");
+    }
+
+    #[test]
+    fn folds_taa_marbuta_and_alef_maqsura() {
+        assert_eq!(normalize("مدرسة"), "مدرسه");
+        assert_eq!(normalize("على مستشفى"), "علي مستشفي");
+    }
+
+    #[test]
+    fn strips_tashkeel_and_tatweel() {
+        assert_eq!(normalize("مُحَمَّدٌ"), "محمد");
+        assert_eq!(normalize("مَرْحَبًا"), "مرحبا");
+        assert_eq!(normalize("كـــتاب"), "كتاب");
+        // Superscript alef and Quranic annotation marks
+        assert_eq!(normalize("هٰذا"), "هذا");
+        assert_eq!(normalize("الرَّحِيمِ"), "الرحيم");
+        assert_eq!(normalize("قُلْۖ"), "قل");
+    }
+
+    #[test]
+    fn leaves_other_scripts_alone() {
+        assert_eq!(normalize("Café déjà vu, 2024!"), "Café déjà vu, 2024!");
+        assert_eq!(normalize("日本語のテキスト"), "日本語のテキスト");
+        // Same word count, so tokens still line up with the original text
+        let text = "اجتماعُ الميزانيةِ يوم الأحد";
+        assert_eq!(normalize(text).split(' ').count(), text.split(' ').count());
+    }
+
+    #[test]
+    fn words_must_all_match() {
+        assert_eq!(matching("cat dog"), Some(r#""cat" "dog""#.to_string()));
+        assert_eq!(matching("cat AND dog"), Some(r#""cat" "dog""#.to_string()));
+        assert_eq!(matching("   "), None);
+        assert_eq!(parse("", false), SearchQuery::default());
+    }
+
+    #[test]
+    fn phrases_stay_together() {
+        assert_eq!(matching(r#""new york" pizza"#), Some(r#""new york" "pizza""#.to_string()));
+        // An unclosed quote runs to the end
+        assert_eq!(matching(r#"pizza "new york"#), Some(r#""pizza" "new york""#.to_string()));
+        // Keywords and punctuation inside a phrase are plain text
+        assert_eq!(matching(r#""this OR that: -x""#), Some(r#""this OR that: -x""#.to_string()));
+    }
+
+    #[test]
+    fn or_binds_tighter_than_and() {
+        assert_eq!(matching("cat OR dog food"), Some(r#"("cat" OR "dog") "food""#.to_string()));
+        assert_eq!(matching("a OR b OR c"), Some(r#"("a" OR "b" OR "c")"#.to_string()));
+        // Lowercase or is a word, and a dangling OR is ignored
+        assert_eq!(matching("cat or dog"), Some(r#""cat" "or" "dog""#.to_string()));
+        assert_eq!(matching("OR cat"), Some(r#""cat""#.to_string()));
+        assert_eq!(matching("cat OR"), Some(r#""cat""#.to_string()));
+    }
+
+    #[test]
+    fn not_and_minus_exclude() {
+        let expected = Some(r#"("cat") NOT ("dog")"#.to_string());
+        assert_eq!(matching("cat -dog"), expected);
+        assert_eq!(matching("cat NOT dog"), expected);
+        assert_eq!(matching("cat -dog -bird"), Some(r#"("cat") NOT ("dog" OR "bird")"#.to_string()));
+        assert_eq!(matching(r#"cat -"hot dog""#), Some(r#"("cat") NOT ("hot dog")"#.to_string()));
+        // An excluded item can't be an OR alternative
+        assert_eq!(matching("cat OR -dog"), expected);
+    }
+
+    #[test]
+    fn only_exclusions_leave_matching_open() {
+        assert_eq!(parse("-dog NOT bird", false), SearchQuery {
+            matching: None,
+            excluding: Some(r#""dog" OR "bird""#.to_string()),
+        });
+        // A minus with nothing after it excludes nothing
+        assert_eq!(parse("cat -", false), SearchQuery {
+            matching: Some(r#""cat""#.to_string()),
+            excluding: None,
+        });
+    }
+
+    #[test]
+    fn trailing_star_matches_by_prefix() {
+        assert_eq!(matching("budg*"), Some(r#""budg"*"#.to_string()));
+        assert_eq!(matching(r#""new yo"*"#), Some(r#""new yo"*"#.to_string()));
+        assert_eq!(matching("*"), None);
+        // The trigram index already matches inside words
+        assert_eq!(parse("budg*", true).matching, Some(r#""budg""#.to_string()));
+    }
+
+    #[test]
+    fn quotes_inside_items_are_escaped() {
+        assert_eq!(fts_item(r#"say "hi""#, false), Some(r#""say ""hi""""#.to_string()));
+        assert_eq!(fts_item("...", false), None);
+    }
+
+    #[test]
+    fn searches_are_normalized() {
+        assert_eq!(matching("المدرسة إلى"), Some(r#""المدرسه" "الي""#.to_string()));
+        assert_eq!(matching("مُحَمَّد*"), Some(r#""محمد"*"#.to_string()));
+    }
+
+    #[test]
+    fn any_word_matches_each_distinct_word() {
+        assert_eq!(
+            any_word("What about the budget? The BUDGET!"),
+            Some(r#""about" OR "budget" OR "the" OR "what""#.to_string())
+        );
+        assert_eq!(any_word("?!"), None);
+    }
+}
@@ -1,13 +1,544 @@
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::fs;
+use std::time::Duration;
 use keyring::Entry;
 
 const SERVICE_NAME: &str = "Visper";
 const KEYRING_USER: &str = "api_key";
+const OPENAI_KEYRING_USER: &str = "openai_api_key";
+const PROXY_KEYRING_USER: &str = "proxy_password";
+
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+
+const SAFETY_CATEGORIES: &[&str] = &[
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+    "HARM_CATEGORY_CIVIC_INTEGRITY",
+];
+
+const SAFETY_THRESHOLDS: &[&str] = &[
+    "BLOCK_NONE",
+    "BLOCK_ONLY_HIGH",
+    "BLOCK_MEDIUM_AND_ABOVE",
+    "BLOCK_LOW_AND_ABOVE",
+    "OFF",
+];
+
+/// Thinking budgets a model accepts besides -1 (dynamic). models.list only
+/// says whether a model thinks, so the ranges come from the model docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThinkingLimits {
+    pub min: i32,
+    pub max: i32,
+    /// Whether a budget of 0 turns thinking off
+    #[serde(rename = "canDisable")]
+    pub can_disable: bool,
+}
+
+impl ThinkingLimits {
+    /// Published limits for a model family, `None` when they aren't known
+    pub fn for_model(model: &str) -> Option<Self> {
+        let (min, max, can_disable) = if model.starts_with("gemini-2.5-pro") {
+            (128, 32768, false)
+        } else if model.starts_with("gemini-2.5-flash-lite") {
+            (512, 24576, true)
+        } else if model.starts_with("gemini-2.5-flash") {
+            (1, 24576, true)
+        } else {
+            return None;
+        };
+        Some(Self { min, max, can_disable })
+    }
+
+    pub fn allows(&self, budget: i32) -> bool {
+        budget == -1 || (budget == 0 && self.can_disable) || (self.min..=self.max).contains(&budget)
+    }
+
+    /// Nearest budget the model accepts: out-of-range budgets are clamped, and
+    /// 0 becomes `None` (the model's default) where thinking can't be turned off
+    pub fn fit(&self, budget: i32) -> Option<i32> {
+        if self.allows(budget) {
+            Some(budget)
+        } else if budget > 0 {
+            Some(budget.clamp(self.min, self.max))
+        } else {
+            None
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let disable = if self.can_disable { ", 0 (off)" } else { "" };
+        format!("-1 (dynamic){} or between {} and {}", disable, self.min, self.max)
+    }
+}
+
+/// What the dictation pipeline does with the recorded speech
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DictationMode {
+    /// Clean transcription in the spoken language
+    #[default]
+    Clean,
+    /// Condensed summary of what was said
+    Summarize,
+    /// Transcribe and translate into `Settings::target_language`
+    Translate,
+}
+
+impl DictationMode {
+    /// Serialized name, as stored in history
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DictationMode::Clean => "clean",
+            DictationMode::Summarize => "summarize",
+            DictationMode::Translate => "translate",
+        }
+    }
+}
+
+/// A transcription backend in the fallback chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// Gemini Developer API with an API key
+    Gemini,
+    /// Vertex AI with a service-account credential
+    Vertex,
+    /// Any endpoint implementing OpenAI's `/audio/transcriptions`
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// A local whisper.cpp server
+    Whisper,
+}
+
+impl Provider {
+    /// Gemini and Vertex AI share the generateContent pipeline and support every dictation mode
+    pub fn is_generate_content(self) -> bool {
+        matches!(self, Provider::Gemini | Provider::Vertex)
+    }
+
+    /// The fallback providers only transcribe, so they can't honor other modes
+    pub fn supports_mode(self, mode: DictationMode) -> bool {
+        mode == DictationMode::Clean || self.is_generate_content()
+    }
+
+    /// Serialized name, as stored in history
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Provider::Gemini => "gemini",
+            Provider::Vertex => "vertex",
+            Provider::OpenAi => "openai",
+            Provider::Whisper => "whisper",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Provider::Gemini => "Gemini",
+            Provider::Vertex => "Vertex AI",
+            Provider::OpenAi => "OpenAI-compatible",
+            Provider::Whisper => "Local Whisper",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenAiSettings {
+    /// API root, e.g. `https://api.openai.com/v1`
+    #[serde(rename = "baseUrl")]
+    pub base_url: String,
+    pub model: String,
+}
+
+impl Default for OpenAiSettings {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.openai.com/v1".to_string(),
+            model: "whisper-1".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhisperSettings {
+    /// whisper.cpp server inference endpoint
+    pub url: String,
+}
+
+impl Default for WhisperSettings {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8080/inference".to_string(),
+        }
+    }
+}
+
+/// When to stop trying a provider that keeps failing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures after which a provider is skipped
+    #[serde(rename = "failureThreshold")]
+    pub failure_threshold: u32,
+    /// How long a tripped provider is skipped before it gets another try
+    #[serde(rename = "cooldownSeconds")]
+    pub cooldown_seconds: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_seconds: 300,
+        }
+    }
+}
+
+/// Proxy, TLS and timeout options shared by every provider's HTTP client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// HTTP(S) proxy for all provider requests, e.g. `http://proxy.corp:3128`
+    #[serde(rename = "proxyUrl")]
+    pub proxy_url: Option<String>,
+    /// Proxy user for basic auth; the password is kept in the keyring
+    #[serde(rename = "proxyUsername")]
+    pub proxy_username: Option<String>,
+    /// PEM file with extra root certificates, e.g. a corporate TLS inspection CA
+    #[serde(rename = "caBundlePath")]
+    pub ca_bundle_path: Option<String>,
+    #[serde(rename = "connectTimeoutSeconds")]
+    pub connect_timeout_seconds: u64,
+    /// Timeout for a request without audio, and the base for one with audio
+    #[serde(rename = "timeoutSeconds")]
+    pub timeout_seconds: u64,
+    /// Added to the timeout for every minute of recorded audio
+    #[serde(rename = "timeoutPerAudioMinuteSeconds")]
+    pub timeout_per_audio_minute_seconds: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            proxy_url: None,
+            proxy_username: None,
+            ca_bundle_path: None,
+            connect_timeout_seconds: 10,
+            timeout_seconds: 60,
+            timeout_per_audio_minute_seconds: 60,
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// Request timeout for a recording of the given length
+    pub fn request_timeout(&self, audio_length: Option<Duration>) -> Duration {
+        let per_minute = self.timeout_per_audio_minute_seconds as f64;
+        let audio_minutes = audio_length.map_or(0.0, |d| d.as_secs_f64() / 60.0);
+        Duration::from_secs(self.timeout_seconds) + Duration::from_secs_f64(per_minute * audio_minutes)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(url) = &self.proxy_url {
+            validate_http_url(url)?;
+        }
+        if let Some(path) = &self.ca_bundle_path {
+            if !std::path::Path::new(path).is_file() {
+                anyhow::bail!("CA bundle not found: {}", path);
+            }
+        }
+        if self.connect_timeout_seconds == 0 || self.timeout_seconds == 0 {
+            anyhow::bail!("Timeouts must be at least 1 second");
+        }
+        Ok(())
+    }
+}
+
+fn validate_http_url(url: &str) -> Result<()> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) || url.len() <= "https://".len() {
+        anyhow::bail!("Invalid URL: {:?}", url);
+    }
+    Ok(())
+}
+
+/// "Continue" mode: each dictation continues the previous entry, with that
+/// entry's text passed to the model as context
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContinuationSettings {
+    pub enabled: bool,
+    /// Only the last this many characters are sent; `None` sends the whole entry
+    #[serde(rename = "contextChars")]
+    pub context_chars: Option<usize>,
+}
+
+impl Default for ContinuationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            context_chars: Some(2000),
+        }
+    }
+}
+
+impl ContinuationSettings {
+    /// Context for a dictation continuing `previous`; none when continue mode is off
+    pub fn context_for<'a>(&self, previous: Option<&'a str>) -> Option<&'a str> {
+        previous.filter(|_| self.enabled).map(|text| self.context_from(text))
+    }
+
+    /// The tail of `text` to pass as context, cut at a word boundary
+    pub fn context_from<'a>(&self, text: &'a str) -> &'a str {
+        let limit = match self.context_chars {
+            Some(limit) => limit,
+            None => return text,
+        };
+        let skip = text.chars().count().saturating_sub(limit);
+        if skip == 0 {
+            return text;
+        }
+
+        let start = text.char_indices().nth(skip).map_or(text.len(), |(i, _)| i);
+        let tail = &text[start..];
+        match tail.find(char::is_whitespace) {
+            Some(i) => tail[i..].trim_start(),
+            None => tail,
+        }
+    }
+}
+
+/// Whether recordings are kept for re-transcription, and for how long
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioRetentionSettings {
+    pub enabled: bool,
+    /// Oldest recordings are removed once the stored audio exceeds this size
+    #[serde(rename = "maxSizeMb")]
+    pub max_size_mb: u64,
+    /// Recordings older than this are removed; `None` keeps them until the size cap
+    #[serde(rename = "maxAgeDays")]
+    pub max_age_days: Option<u32>,
+    /// Recordings of favorite entries are exempt from both limits
+    #[serde(rename = "keepFavorites")]
+    pub keep_favorites: bool,
+}
+
+impl Default for AudioRetentionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size_mb: 500,
+            max_age_days: Some(30),
+            keep_favorites: true,
+        }
+    }
+}
+
+/// How the Gemini API key is sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthScheme {
+    /// `x-goog-api-key: <key>`, as the Gemini API expects
+    #[default]
+    #[serde(rename = "apiKey")]
+    ApiKey,
+    /// `Authorization: Bearer <key>`
+    Bearer,
+    /// The key as the value of `GeminiSettings::auth_header`
+    Header,
+}
+
+/// Where Gemini API requests go. Lets them go through a gateway that speaks
+/// the Gemini REST API at another host with its own auth header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeminiSettings {
+    /// API root that `models/...` paths are appended to
+    #[serde(rename = "baseUrl")]
+    pub base_url: String,
+    #[serde(rename = "authScheme")]
+    pub auth_scheme: AuthScheme,
+    /// Header name for `AuthScheme::Header`
+    #[serde(rename = "authHeader")]
+    pub auth_header: Option<String>,
+}
+
+impl Default for GeminiSettings {
+    fn default() -> Self {
+        Self {
+            base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            auth_scheme: AuthScheme::ApiKey,
+            auth_header: None,
+        }
+    }
+}
+
+impl GeminiSettings {
+    fn validate(&self) -> Result<()> {
+        validate_http_url(&self.base_url)?;
+        if self.auth_scheme == AuthScheme::Header {
+            let name = self.auth_header.as_deref().unwrap_or_default();
+            // RFC 9110 token characters
+            let valid = !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+            if !valid {
+                anyhow::bail!("Invalid auth header name: {:?}", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VertexSettings {
+    /// Path to the service-account JSON key file
+    #[serde(rename = "credentialsPath")]
+    pub credentials_path: Option<String>,
+    /// Overrides the project ID from the service-account file
+    #[serde(rename = "projectId")]
+    pub project_id: Option<String>,
+    pub region: String,
+    /// Replaces `https://{region}-aiplatform.googleapis.com`, e.g. for a private endpoint
+    pub endpoint: Option<String>,
+}
+
+impl Default for VertexSettings {
+    fn default() -> Self {
+        Self {
+            credentials_path: None,
+            project_id: None,
+            region: "us-central1".to_string(),
+            endpoint: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: String,
+}
+
+/// Gemini generation parameters. `None` leaves the model's default in place.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationSettings {
+    pub temperature: Option<f32>,
+    #[serde(rename = "maxOutputTokens")]
+    pub max_output_tokens: Option<u32>,
+    /// Tokens the model may spend thinking: 0 disables it, -1 lets the model decide
+    #[serde(rename = "thinkingBudget")]
+    pub thinking_budget: Option<i32>,
+    #[serde(rename = "safetySettings")]
+    pub safety_settings: Option<Vec<SafetySetting>>,
+}
+
+impl GenerationSettings {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(t) = self.temperature {
+            if !(0.0..=2.0).contains(&t) {
+                anyhow::bail!("Temperature must be between 0.0 and 2.0");
+            }
+        }
+        if let Some(max) = self.max_output_tokens {
+            if !(1..=65536).contains(&max) {
+                anyhow::bail!("Max output tokens must be between 1 and 65536");
+            }
+        }
+        if let Some(budget) = self.thinking_budget {
+            if budget < -1 {
+                anyhow::bail!("Thinking budget must be -1 (dynamic) or at least 0");
+            }
+        }
+        for setting in self.safety_settings.iter().flatten() {
+            if !SAFETY_CATEGORIES.contains(&setting.category.as_str()) {
+                anyhow::bail!("Unknown safety category: {}", setting.category);
+            }
+            if !SAFETY_THRESHOLDS.contains(&setting.threshold.as_str()) {
+                anyhow::bail!("Unknown safety threshold: {}", setting.threshold);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the thinking budget against what `model` accepts, when known
+    pub fn validate_for_model(&self, model: &str) -> Result<()> {
+        self.validate()?;
+        if let (Some(budget), Some(limits)) = (self.thinking_budget, ThinkingLimits::for_model(model)) {
+            if !limits.allows(budget) {
+                anyhow::bail!("Thinking budget for {} must be {}", model, limits.describe());
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring the thinking budget within `model`'s limits. Returns false if it changed.
+    pub fn fit_to_model(&mut self, model: &str) -> bool {
+        let (Some(budget), Some(limits)) = (self.thinking_budget, ThinkingLimits::for_model(model)) else {
+            return true;
+        };
+        self.thinking_budget = limits.fit(budget);
+        self.thinking_budget == Some(budget)
+    }
+
+    /// Layer `overrides` on top of these settings, field by field
+    pub fn merged_with(&self, overrides: Option<&GenerationSettings>) -> GenerationSettings {
+        let Some(o) = overrides else { return self.clone() };
+        GenerationSettings {
+            temperature: o.temperature.or(self.temperature),
+            max_output_tokens: o.max_output_tokens.or(self.max_output_tokens),
+            thinking_budget: o.thinking_budget.or(self.thinking_budget),
+            safety_settings: o.safety_settings.clone().or_else(|| self.safety_settings.clone()),
+        }
+    }
+}
+
+fn validate_model_name(model: &str) -> Result<()> {
+    let valid = !model.is_empty()
+        && model.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+    if !valid {
+        anyhow::bail!("Invalid model name: {:?}", model);
+    }
+    Ok(())
+}
+
+/// `None` keeps trashed entries until the trash is emptied
+fn validate_trash_retention(days: Option<u32>) -> Result<()> {
+    if days == Some(0) {
+        anyhow::bail!("Trash must be kept for at least 1 day");
+    }
+    Ok(())
+}
+
+/// Clean and translate only need a transcript, so they skip thinking by default,
+/// while a summary lets the model decide how much to think.
+/// Models that can't turn thinking off keep their own default instead.
+fn default_mode_overrides() -> BTreeMap<DictationMode, GenerationSettings> {
+    let no_thinking = GenerationSettings {
+        thinking_budget: Some(0),
+        ..Default::default()
+    };
+    let dynamic_thinking = GenerationSettings {
+        thinking_budget: Some(-1),
+        ..Default::default()
+    };
+    BTreeMap::from([
+        (DictationMode::Clean, no_thinking.clone()),
+        (DictationMode::Summarize, dynamic_thinking),
+        (DictationMode::Translate, no_thinking),
+    ])
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub theme: String,
     #[serde(rename = "soundEnabled")]
@@ -15,6 +546,59 @@ pub struct Settings {
     #[serde(rename = "firstLaunchComplete")]
     pub first_launch_complete: bool,
     pub hotkey: String,
+    pub mode: DictationMode,
+    /// ISO 639-1 code of the language translations are written in
+    #[serde(rename = "targetLanguage")]
+    pub target_language: String,
+    /// In translate mode, also return the transcript in the spoken language
+    #[serde(rename = "keepOriginal")]
+    pub keep_original: bool,
+    pub continuation: ContinuationSettings,
+    #[serde(rename = "audioRetention")]
+    pub audio_retention: AudioRetentionSettings,
+    /// Send history text to Gemini for embeddings so it can be searched by meaning
+    #[serde(rename = "semanticSearch")]
+    pub semantic_search: bool,
+    /// Trashed history entries are deleted for good after this; `None` keeps them until the trash is emptied
+    #[serde(rename = "trashRetentionDays")]
+    pub trash_retention_days: Option<u32>,
+    /// ISO 639-1 code the speaker most likely uses, passed to the model as a hint
+    #[serde(rename = "preferredLanguage")]
+    pub preferred_language: Option<String>,
+    /// ISO 639-1 codes the speech is restricted to; empty means any language
+    #[serde(rename = "allowedLanguages")]
+    pub allowed_languages: Vec<String>,
+    /// Providers tried in order until one succeeds
+    pub providers: Vec<Provider>,
+    pub gemini: GeminiSettings,
+    pub vertex: VertexSettings,
+    pub openai: OpenAiSettings,
+    pub whisper: WhisperSettings,
+    #[serde(rename = "circuitBreaker")]
+    pub circuit_breaker: CircuitBreakerSettings,
+    pub network: NetworkSettings,
+    /// Gemini model used for generateContent requests
+    pub model: String,
+    pub generation: GenerationSettings,
+    /// Per-mode generation parameters, applied on top of `generation`
+    #[serde(rename = "modeOverrides")]
+    pub mode_overrides: BTreeMap<DictationMode, GenerationSettings>,
+}
+
+impl Settings {
+    /// Effective generation parameters for a dictation mode
+    pub fn generation_for(&self, mode: DictationMode) -> GenerationSettings {
+        self.generation.merged_with(self.mode_overrides.get(&mode))
+    }
+
+    fn validate_generation(&self) -> Result<()> {
+        validate_model_name(&self.model)?;
+        self.generation.validate()?;
+        for overrides in self.mode_overrides.values() {
+            overrides.validate()?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for Settings {
@@ -24,10 +608,65 @@ impl Default for Settings {
             sound_enabled: true,
             first_launch_complete: false,
             hotkey: "Super+J".to_string(),
+            mode: DictationMode::Clean,
+            target_language: "en".to_string(),
+            keep_original: false,
+            semantic_search: false,
+            trash_retention_days: Some(30),
+            continuation: ContinuationSettings::default(),
+            audio_retention: AudioRetentionSettings::default(),
+            preferred_language: None,
+            allowed_languages: Vec::new(),
+            providers: vec![Provider::Gemini],
+            gemini: GeminiSettings::default(),
+            vertex: VertexSettings::default(),
+            openai: OpenAiSettings::default(),
+            whisper: WhisperSettings::default(),
+            circuit_breaker: CircuitBreakerSettings::default(),
+            network: NetworkSettings::default(),
+            model: DEFAULT_MODEL.to_string(),
+            generation: GenerationSettings::default(),
+            mode_overrides: default_mode_overrides(),
         }
     }
 }
 
+/// Settings from a settings file, taking each field that parses and defaulting
+/// the rest, so one bad value doesn't cost the others. The flag is false when
+/// anything was dropped.
+fn parse_settings(content: &str) -> (Settings, bool) {
+    let fields = match serde_json::from_str(content) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        Ok(_) => {
+            eprintln!("[Visper] Settings file is not a JSON object. Using defaults.");
+            return (Settings::default(), false);
+        }
+        Err(e) => {
+            eprintln!("[Visper] Failed to parse settings ({}). Using defaults.", e);
+            return (Settings::default(), false);
+        }
+    };
+
+    let mut merged = match serde_json::to_value(Settings::default()) {
+        Ok(serde_json::Value::Object(defaults)) => defaults,
+        _ => return (Settings::default(), false),
+    };
+    let mut complete = true;
+    for (key, value) in fields {
+        let mut candidate = merged.clone();
+        candidate.insert(key.clone(), value);
+        match serde_json::from_value::<Settings>(serde_json::Value::Object(candidate.clone())) {
+            Ok(_) => merged = candidate,
+            Err(e) => {
+                eprintln!("[Visper] Ignoring invalid setting {:?} ({}). Using its default.", key, e);
+                complete = false;
+            }
+        }
+    }
+    let settings = serde_json::from_value(serde_json::Value::Object(merged)).unwrap_or_default();
+    (settings, complete)
+}
+
 pub struct SettingsService {
     settings: Settings,
     config_path: PathBuf,
@@ -35,8 +674,12 @@ pub struct SettingsService {
 
 impl SettingsService {
     pub fn new() -> Result<Self> {
-        let config_path = Self::get_config_path()?;
+        Self::load(Self::get_config_path()?, Self::check_api_key_exists)
+    }
 
+    /// Read the settings at `config_path`, repair what can't be used and write them back.
+    /// `api_key_exists` is only asked when setup looks unfinished.
+    fn load(config_path: PathBuf, api_key_exists: impl FnOnce() -> bool) -> Result<Self> {
         // Ensure directory exists
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)?;
@@ -44,18 +687,40 @@ impl SettingsService {
 
         let mut settings: Settings = if config_path.exists() {
             let content = fs::read_to_string(&config_path)?;
-            serde_json::from_str(&content).unwrap_or_default()
+            let (settings, complete) = parse_settings(&content);
+            if !complete {
+                // Keep what was dropped, since saving below overwrites the file
+                let backup = config_path.with_extension("json.bak");
+                eprintln!("[Visper] Settings had unreadable values; keeping the original as {}", backup.display());
+                fs::write(&backup, &content)?;
+            }
+            settings
         } else {
             Settings::default()
         };
 
         // Recovery: if firstLaunchComplete is false but API key exists,
         // the user already completed setup before (settings.json was likely deleted/corrupted)
-        if !settings.first_launch_complete && Self::check_api_key_exists() {
+        if !settings.first_launch_complete && api_key_exists() {
             eprintln!("[Visper] Settings recovery: API key found in keyring but firstLaunchComplete=false. Auto-recovering.");
             settings.first_launch_complete = true;
         }
 
+        // A hand-edited settings.json may carry values the API would reject
+        if let Err(e) = settings.validate_generation() {
+            eprintln!("[Visper] Invalid generation settings ({}). Resetting them to defaults.", e);
+            let defaults = Settings::default();
+            settings.model = defaults.model;
+            settings.generation = defaults.generation;
+            settings.mode_overrides = defaults.mode_overrides;
+        }
+
+        // Keeping trash for 0 days would empty it on every start
+        if let Err(e) = validate_trash_retention(settings.trash_retention_days) {
+            eprintln!("[Visper] {}. Resetting trash retention to the default.", e);
+            settings.trash_retention_days = Settings::default().trash_retention_days;
+        }
+
         let service = Self { settings, config_path };
         service.save()?;
 
@@ -95,6 +760,25 @@ impl SettingsService {
             "soundEnabled" => Some(serde_json::Value::Bool(self.settings.sound_enabled)),
             "firstLaunchComplete" => Some(serde_json::Value::Bool(self.settings.first_launch_complete)),
             "hotkey" => Some(serde_json::Value::String(self.settings.hotkey.clone())),
+            "mode" => serde_json::to_value(self.settings.mode).ok(),
+            "targetLanguage" => Some(serde_json::Value::String(self.settings.target_language.clone())),
+            "keepOriginal" => Some(serde_json::Value::Bool(self.settings.keep_original)),
+            "continuation" => serde_json::to_value(&self.settings.continuation).ok(),
+            "audioRetention" => serde_json::to_value(&self.settings.audio_retention).ok(),
+            "semanticSearch" => Some(serde_json::Value::Bool(self.settings.semantic_search)),
+            "trashRetentionDays" => serde_json::to_value(self.settings.trash_retention_days).ok(),
+            "preferredLanguage" => serde_json::to_value(&self.settings.preferred_language).ok(),
+            "allowedLanguages" => serde_json::to_value(&self.settings.allowed_languages).ok(),
+            "providers" => serde_json::to_value(&self.settings.providers).ok(),
+            "gemini" => serde_json::to_value(&self.settings.gemini).ok(),
+            "vertex" => serde_json::to_value(&self.settings.vertex).ok(),
+            "openai" => serde_json::to_value(&self.settings.openai).ok(),
+            "whisper" => serde_json::to_value(&self.settings.whisper).ok(),
+            "circuitBreaker" => serde_json::to_value(&self.settings.circuit_breaker).ok(),
+            "network" => serde_json::to_value(&self.settings.network).ok(),
+            "model" => Some(serde_json::Value::String(self.settings.model.clone())),
+            "generation" => serde_json::to_value(&self.settings.generation).ok(),
+            "modeOverrides" => serde_json::to_value(&self.settings.mode_overrides).ok(),
             _ => None,
         }
     }
@@ -121,6 +805,143 @@ impl SettingsService {
                     self.settings.hotkey = s.to_string();
                 }
             }
+            "mode" => {
+                self.settings.mode = serde_json::from_value(value)?;
+            }
+            "targetLanguage" => {
+                if let Some(s) = value.as_str() {
+                    let code = s.trim().to_lowercase();
+                    if code.is_empty() {
+                        anyhow::bail!("Target language cannot be empty");
+                    }
+                    self.settings.target_language = code;
+                }
+            }
+            "keepOriginal" => {
+                if let Some(b) = value.as_bool() {
+                    self.settings.keep_original = b;
+                }
+            }
+            "semanticSearch" => {
+                if let Some(b) = value.as_bool() {
+                    self.settings.semantic_search = b;
+                }
+            }
+            "trashRetentionDays" => {
+                let days: Option<u32> = serde_json::from_value(value)?;
+                validate_trash_retention(days)?;
+                self.settings.trash_retention_days = days;
+            }
+            "preferredLanguage" => {
+                // null or an empty string clears the hint
+                self.settings.preferred_language = value.as_str()
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty());
+            }
+            "allowedLanguages" => {
+                let codes: Vec<String> = serde_json::from_value(value)?;
+                let mut allowed: Vec<String> = Vec::new();
+                for code in codes.into_iter().map(|s| s.trim().to_lowercase()) {
+                    if !code.is_empty() && !allowed.contains(&code) {
+                        allowed.push(code);
+                    }
+                }
+                self.settings.allowed_languages = allowed;
+            }
+            "providers" => {
+                let providers: Vec<Provider> = serde_json::from_value(value)?;
+                if providers.is_empty() {
+                    anyhow::bail!("At least one provider must be enabled");
+                }
+                if providers.iter().enumerate().any(|(i, p)| providers[..i].contains(p)) {
+                    anyhow::bail!("Each provider can only appear once");
+                }
+                self.settings.providers = providers;
+            }
+            "continuation" => {
+                let continuation: ContinuationSettings = serde_json::from_value(value)?;
+                if continuation.context_chars == Some(0) {
+                    anyhow::bail!("Context length must be at least 1 character");
+                }
+                self.settings.continuation = continuation;
+            }
+            "audioRetention" => {
+                let retention: AudioRetentionSettings = serde_json::from_value(value)?;
+                if retention.max_size_mb == 0 {
+                    anyhow::bail!("Audio size limit must be at least 1 MB");
+                }
+                if retention.max_age_days == Some(0) {
+                    anyhow::bail!("Audio age limit must be at least 1 day");
+                }
+                self.settings.audio_retention = retention;
+            }
+            "gemini" => {
+                let gemini: GeminiSettings = serde_json::from_value(value)?;
+                gemini.validate()?;
+                self.settings.gemini = gemini;
+            }
+            "openai" => {
+                let openai: OpenAiSettings = serde_json::from_value(value)?;
+                validate_http_url(&openai.base_url)?;
+                if openai.model.trim().is_empty() {
+                    anyhow::bail!("OpenAI-compatible model cannot be empty");
+                }
+                self.settings.openai = openai;
+            }
+            "whisper" => {
+                let whisper: WhisperSettings = serde_json::from_value(value)?;
+                validate_http_url(&whisper.url)?;
+                self.settings.whisper = whisper;
+            }
+            "circuitBreaker" => {
+                let breaker: CircuitBreakerSettings = serde_json::from_value(value)?;
+                if breaker.failure_threshold == 0 {
+                    anyhow::bail!("Failure threshold must be at least 1");
+                }
+                self.settings.circuit_breaker = breaker;
+            }
+            "network" => {
+                let network: NetworkSettings = serde_json::from_value(value)?;
+                network.validate()?;
+                self.settings.network = network;
+            }
+            "vertex" => {
+                let vertex: VertexSettings = serde_json::from_value(value)?;
+                let region_valid = !vertex.region.is_empty()
+                    && vertex.region.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+                if !region_valid {
+                    anyhow::bail!("Invalid Vertex AI region: {:?}", vertex.region);
+                }
+                self.settings.vertex = vertex;
+            }
+            "model" => {
+                if let Some(s) = value.as_str() {
+                    let model = s.trim().trim_start_matches("models/");
+                    validate_model_name(model)?;
+                    self.settings.model = model.to_string();
+                    // Budgets set for the previous model may be outside this one's limits
+                    if !self.settings.generation.fit_to_model(model) {
+                        eprintln!("[Visper] Thinking budget adjusted to {:?} for {}", self.settings.generation.thinking_budget, model);
+                    }
+                    for (mode, generation) in self.settings.mode_overrides.iter_mut() {
+                        if !generation.fit_to_model(model) {
+                            eprintln!("[Visper] {} thinking budget adjusted to {:?} for {}", mode.as_str(), generation.thinking_budget, model);
+                        }
+                    }
+                }
+            }
+            "generation" => {
+                let generation: GenerationSettings = serde_json::from_value(value)?;
+                generation.validate_for_model(&self.settings.model)?;
+                self.settings.generation = generation;
+            }
+            "modeOverrides" => {
+                let overrides: BTreeMap<DictationMode, GenerationSettings> = serde_json::from_value(value)?;
+                for o in overrides.values() {
+                    o.validate_for_model(&self.settings.model)?;
+                }
+                self.settings.mode_overrides = overrides;
+            }
             _ => {}
         }
         self.save()
@@ -128,25 +949,40 @@ impl SettingsService {
 
     // Secure API key storage using system keyring (Windows Credential Manager)
     pub fn get_api_key(&self) -> Result<Option<String>> {
-        let entry = Entry::new(SERVICE_NAME, KEYRING_USER)?;
-        match entry.get_password() {
-            Ok(password) => Ok(Some(password)),
-            Err(keyring::Error::NoEntry) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        read_secret(KEYRING_USER)
     }
 
     pub fn set_api_key(&mut self, key: &str) -> Result<()> {
-        let entry = Entry::new(SERVICE_NAME, KEYRING_USER)?;
-        entry.set_password(key)?;
-        Ok(())
+        write_secret(KEYRING_USER, key)
     }
 
     pub fn clear_api_key(&self) -> Result<()> {
-        let entry = Entry::new(SERVICE_NAME, KEYRING_USER)?;
-        // Ignore error if credential doesn't exist
-        let _ = entry.delete_credential();
-        Ok(())
+        delete_secret(KEYRING_USER)
+    }
+
+    /// Key for the OpenAI-compatible fallback provider, kept apart from the Gemini key
+    pub fn get_openai_api_key(&self) -> Result<Option<String>> {
+        read_secret(OPENAI_KEYRING_USER)
+    }
+
+    pub fn set_openai_api_key(&mut self, key: &str) -> Result<()> {
+        write_secret(OPENAI_KEYRING_USER, key)
+    }
+
+    pub fn clear_openai_api_key(&self) -> Result<()> {
+        delete_secret(OPENAI_KEYRING_USER)
+    }
+
+    pub fn get_proxy_password(&self) -> Result<Option<String>> {
+        read_secret(PROXY_KEYRING_USER)
+    }
+
+    pub fn set_proxy_password(&mut self, password: &str) -> Result<()> {
+        write_secret(PROXY_KEYRING_USER, password)
+    }
+
+    pub fn clear_proxy_password(&self) -> Result<()> {
+        delete_secret(PROXY_KEYRING_USER)
     }
 
     pub fn is_first_launch(&self) -> bool {
@@ -166,3 +1002,262 @@ impl SettingsService {
         Ok(())
     }
 }
+
+fn read_secret(user: &str) -> Result<Option<String>> {
+    let entry = Entry::new(SERVICE_NAME, user)?;
+    match entry.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_secret(user: &str, secret: &str) -> Result<()> {
+    let entry = Entry::new(SERVICE_NAME, user)?;
+    entry.set_password(secret)?;
+    Ok(())
+}
+
+fn delete_secret(user: &str) -> Result<()> {
+    let entry = Entry::new(SERVICE_NAME, user)?;
+    // Ignore error if credential doesn't exist
+    let _ = entry.delete_credential();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(dir: &tempfile::TempDir) -> SettingsService {
+        SettingsService { settings: Settings::default(), config_path: dir.path().join("settings.json") }
+    }
+
+    fn saved(dir: &tempfile::TempDir) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(dir.path().join("settings.json")).unwrap()).unwrap()
+    }
+
+    /// A service loaded from a settings file holding `json`, with no API key stored
+    fn load(dir: &tempfile::TempDir, json: &str) -> SettingsService {
+        let config_path = dir.path().join("settings.json");
+        fs::write(&config_path, json).unwrap();
+        SettingsService::load(config_path, || false).unwrap()
+    }
+
+    #[test]
+    fn loads_summarize_mode_and_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = load(&dir, r#"{
+            "mode": "summarize",
+            "modeOverrides": {
+                "clean": {"thinkingBudget": 0},
+                "summarize": {"thinkingBudget": 2048, "temperature": 0.3}
+            }
+        }"#);
+        let settings = service.get_all();
+        assert_eq!(settings.mode, DictationMode::Summarize);
+        assert_eq!(settings.generation_for(DictationMode::Summarize).thinking_budget, Some(2048));
+        assert_eq!(settings.generation_for(DictationMode::Clean).thinking_budget, Some(0));
+        assert_eq!(saved(&dir)["mode"], "summarize");
+        assert_eq!(saved(&dir)["modeOverrides"]["summarize"]["thinkingBudget"], 2048);
+        assert!(!dir.path().join("settings.json.bak").exists());
+    }
+
+    #[test]
+    fn summarize_thinks_and_clean_does_not_by_default() {
+        let settings = Settings::default();
+        assert_eq!(settings.generation_for(DictationMode::Clean).thinking_budget, Some(0));
+        assert_eq!(settings.generation_for(DictationMode::Translate).thinking_budget, Some(0));
+        assert_eq!(settings.generation_for(DictationMode::Summarize).thinking_budget, Some(-1));
+    }
+
+    #[test]
+    fn an_invalid_field_keeps_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = r#"{"theme": "dark", "hotkey": "Alt+D", "mode": "shout", "soundEnabled": "yes"}"#;
+        let service = load(&dir, original);
+        let settings = service.get_all();
+        assert_eq!(settings.theme, "dark");
+        assert_eq!(settings.hotkey, "Alt+D");
+        assert_eq!(settings.mode, DictationMode::Clean);
+        assert_eq!(settings.sound_enabled, Settings::default().sound_enabled);
+        assert_eq!(saved(&dir)["theme"], "dark");
+        // The file as it was is kept next to the rewritten one
+        assert_eq!(fs::read_to_string(dir.path().join("settings.json.bak")).unwrap(), original);
+    }
+
+    #[test]
+    fn an_unreadable_file_is_backed_up_before_defaults_are_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = load(&dir, "{\"theme\": ");
+        assert_eq!(service.get_all().theme, Settings::default().theme);
+        assert_eq!(fs::read_to_string(dir.path().join("settings.json.bak")).unwrap(), "{\"theme\": ");
+    }
+
+    fn budget(budget: i32) -> GenerationSettings {
+        GenerationSettings { thinking_budget: Some(budget), ..Default::default() }
+    }
+
+    #[test]
+    fn thinking_budget_is_validated_per_model() {
+        assert!(budget(0).validate_for_model("gemini-2.5-flash").is_ok());
+        assert!(budget(24576).validate_for_model("gemini-2.5-flash").is_ok());
+        assert!(budget(24577).validate_for_model("gemini-2.5-flash").is_err());
+
+        let err = budget(0).validate_for_model("gemini-2.5-pro").unwrap_err().to_string();
+        assert!(err.contains("between 128 and 32768"), "{}", err);
+        assert!(!err.contains("(off)"), "{}", err);
+        assert!(budget(127).validate_for_model("gemini-2.5-pro").is_err());
+        assert!(budget(32768).validate_for_model("gemini-2.5-pro").is_ok());
+        assert!(budget(-1).validate_for_model("gemini-2.5-pro").is_ok());
+
+        assert!(budget(100).validate_for_model("gemini-2.5-flash-lite").is_err());
+        assert!(budget(0).validate_for_model("gemini-2.5-flash-lite").is_ok());
+
+        // Unknown limits: only the generic check applies
+        assert!(budget(100000).validate_for_model("gemini-next").is_ok());
+        assert!(budget(-2).validate_for_model("gemini-next").is_err());
+    }
+
+    #[test]
+    fn trash_retention_accepts_days_or_forever() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = service(&dir);
+        assert_eq!(service.get("trashRetentionDays"), Some(serde_json::json!(30)));
+
+        service.set("trashRetentionDays", serde_json::json!(1)).unwrap();
+        assert_eq!(service.get_all().trash_retention_days, Some(1));
+        assert_eq!(saved(&dir)["trashRetentionDays"], 1);
+
+        // null keeps trashed entries until the trash is emptied
+        service.set("trashRetentionDays", serde_json::Value::Null).unwrap();
+        assert_eq!(service.get_all().trash_retention_days, None);
+        assert_eq!(service.get("trashRetentionDays"), Some(serde_json::Value::Null));
+        assert!(saved(&dir)["trashRetentionDays"].is_null());
+    }
+
+    #[test]
+    fn trash_retention_rejects_zero_and_invalid_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = service(&dir);
+        service.set("trashRetentionDays", serde_json::json!(7)).unwrap();
+
+        let err = service.set("trashRetentionDays", serde_json::json!(0)).unwrap_err().to_string();
+        assert!(err.contains("at least 1 day"), "{}", err);
+        for invalid in [serde_json::json!(-1), serde_json::json!(1.5), serde_json::json!("7")] {
+            assert!(service.set("trashRetentionDays", invalid.clone()).is_err(), "{}", invalid);
+        }
+        // Rejected values leave the setting and the file alone
+        assert_eq!(service.get_all().trash_retention_days, Some(7));
+        assert_eq!(saved(&dir)["trashRetentionDays"], 7);
+    }
+
+    #[test]
+    fn trash_retention_from_settings_file() {
+        let parse = |json: &str| serde_json::from_str::<Settings>(json).unwrap().trash_retention_days;
+        assert_eq!(parse("{}"), Some(30));
+        assert_eq!(parse(r#"{"trashRetentionDays": null}"#), None);
+        assert_eq!(parse(r#"{"trashRetentionDays": 90}"#), Some(90));
+        assert!(validate_trash_retention(None).is_ok());
+    }
+
+    #[test]
+    fn hand_edited_zero_trash_retention_is_reset_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = load(&dir, r#"{"theme": "dark", "trashRetentionDays": 0}"#);
+        assert_eq!(service.get_all().trash_retention_days, Some(30));
+        assert_eq!(saved(&dir)["trashRetentionDays"], 30);
+        // Other settings are kept, and the value was readable so no backup is made
+        assert_eq!(saved(&dir)["theme"], "dark");
+        assert!(!dir.path().join("settings.json.bak").exists());
+
+        // Keeping trash forever is left alone
+        let service = load(&dir, r#"{"trashRetentionDays": null}"#);
+        assert_eq!(service.get_all().trash_retention_days, None);
+        assert!(saved(&dir)["trashRetentionDays"].is_null());
+    }
+
+    #[test]
+    fn unset_budget_passes_for_every_model() {
+        let generation = GenerationSettings::default();
+        assert!(generation.validate_for_model("gemini-2.5-pro").is_ok());
+        assert!(generation.validate_for_model("gemini-2.5-flash").is_ok());
+    }
+
+    #[test]
+    fn budgets_are_fitted_to_the_model_limits() {
+        let pro = ThinkingLimits::for_model("gemini-2.5-pro").unwrap();
+        assert_eq!(pro.fit(-1), Some(-1));
+        assert_eq!(pro.fit(2048), Some(2048));
+        assert_eq!(pro.fit(16), Some(128));
+        assert_eq!(pro.fit(0), None);
+        let flash = ThinkingLimits::for_model("gemini-2.5-flash").unwrap();
+        assert_eq!(flash.fit(30000), Some(24576));
+        assert_eq!(flash.fit(0), Some(0));
+    }
+
+    #[test]
+    fn changing_the_model_refits_thinking_budgets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = service(&dir);
+        service.set("model", serde_json::json!("gemini-2.5-pro")).unwrap();
+        service.set("generation", serde_json::json!({"thinkingBudget": 32768, "temperature": 0.2})).unwrap();
+        service.set("modeOverrides", serde_json::json!({
+            "summarize": {"thinkingBudget": 30000},
+            "translate": {"thinkingBudget": 1024},
+        })).unwrap();
+
+        // Flash thinks up to 24576 tokens
+        service.set("model", serde_json::json!("gemini-2.5-flash")).unwrap();
+        let settings = service.get_all();
+        assert_eq!(settings.generation.thinking_budget, Some(24576));
+        assert_eq!(settings.generation.temperature, Some(0.2));
+        assert_eq!(settings.mode_overrides[&DictationMode::Summarize].thinking_budget, Some(24576));
+        assert_eq!(settings.mode_overrides[&DictationMode::Translate].thinking_budget, Some(1024));
+        assert_eq!(saved(&dir)["generation"]["thinkingBudget"], 24576);
+
+        // Flash-Lite needs at least 512, and Pro can't turn thinking off
+        service.set("generation", serde_json::json!({"thinkingBudget": 64})).unwrap();
+        service.set("modeOverrides", serde_json::json!({"clean": {"thinkingBudget": 0}})).unwrap();
+        service.set("model", serde_json::json!("gemini-2.5-flash-lite")).unwrap();
+        assert_eq!(service.get_all().generation.thinking_budget, Some(512));
+        service.set("model", serde_json::json!("gemini-2.5-pro")).unwrap();
+        let settings = service.get_all();
+        assert_eq!(settings.generation.thinking_budget, Some(512));
+        assert_eq!(settings.mode_overrides[&DictationMode::Clean].thinking_budget, None);
+        assert!(saved(&dir)["modeOverrides"]["clean"]["thinkingBudget"].is_null());
+        assert!(settings.generation.validate_for_model(&settings.model).is_ok());
+        assert!(settings.mode_overrides.values().all(|o| o.validate_for_model(&settings.model).is_ok()));
+
+        // Limits of unknown models aren't known, so nothing is changed
+        service.set("generation", serde_json::json!({"thinkingBudget": 32768})).unwrap();
+        service.set("model", serde_json::json!("gemini-3-experimental")).unwrap();
+        assert_eq!(service.get_all().generation.thinking_budget, Some(32768));
+    }
+
+    #[test]
+    fn continuation_context_is_the_tail_of_the_previous_entry() {
+        let continuation = ContinuationSettings { enabled: true, context_chars: Some(12) };
+        let previous = "First we met Anna. Then we met Sørensen about the budget";
+        // The cut falls mid-word, so the partial word is dropped
+        assert_eq!(continuation.context_for(Some(previous)), Some("the budget"));
+        assert_eq!(continuation.context_for(Some("short text")), Some("short text"));
+        assert_eq!(continuation.context_for(None), None);
+
+        // Characters, not bytes, are counted
+        let continuation = ContinuationSettings { enabled: true, context_chars: Some(7) };
+        assert_eq!(continuation.context_from("اجتماع الميزانية غدا"), "غدا");
+        assert_eq!(continuation.context_from("ميزانية"), "ميزانية");
+        // A single long word is kept from the cut
+        assert_eq!(continuation.context_from("Donaudampfschifffahrt"), "fffahrt");
+
+        let whole = ContinuationSettings { enabled: true, context_chars: None };
+        assert_eq!(whole.context_for(Some(previous)), Some(previous));
+    }
+
+    #[test]
+    fn no_context_is_sent_when_continue_mode_is_off() {
+        let off = ContinuationSettings { enabled: false, ..Default::default() };
+        assert_eq!(off.context_for(Some("First we met Anna.")), None);
+    }
+}
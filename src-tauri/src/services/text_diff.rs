@@ -0,0 +1,227 @@
+// Word-level diff, used to show what cleanup removed or changed in a transcript.
+
+use serde::{Deserialize, Serialize};
+
+/// Above this many LCS cells the middle of the diff is shown as one replacement
+const MAX_DIFF_CELLS: usize = 16_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    Equal,
+    Removed,
+    Added,
+}
+
+/// A run of consecutive words with the same kind, joined by single spaces
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffSegment {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextDiff {
+    pub segments: Vec<DiffSegment>,
+    #[serde(rename = "removedWords")]
+    pub removed_words: usize,
+    #[serde(rename = "addedWords")]
+    pub added_words: usize,
+}
+
+/// Diff `before` against `after` word by word. Words match when they are equal
+/// ignoring case and surrounding punctuation; matched words are shown as they
+/// appear in `after`.
+pub fn diff_words(before: &str, after: &str) -> TextDiff {
+    let old: Vec<&str> = before.split_whitespace().collect();
+    let new: Vec<&str> = after.split_whitespace().collect();
+    let old_keys: Vec<String> = old.iter().map(|w| match_key(w)).collect();
+    let new_keys: Vec<String> = new.iter().map(|w| match_key(w)).collect();
+
+    let prefix = old_keys.iter().zip(&new_keys).take_while(|(a, b)| a == b).count();
+    let suffix = old_keys[prefix..].iter().rev()
+        .zip(new_keys[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut ops: Vec<(DiffKind, &str)> = new[..prefix].iter().map(|w| (DiffKind::Equal, *w)).collect();
+    ops.extend(diff_middle(
+        &old[prefix..old.len() - suffix],
+        &old_keys[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        &new_keys[prefix..new.len() - suffix],
+    ));
+    ops.extend(new[new.len() - suffix..].iter().map(|w| (DiffKind::Equal, *w)));
+
+    let removed_words = ops.iter().filter(|(kind, _)| *kind == DiffKind::Removed).count();
+    let added_words = ops.iter().filter(|(kind, _)| *kind == DiffKind::Added).count();
+
+    let mut segments: Vec<DiffSegment> = Vec::new();
+    for (kind, word) in ops {
+        match segments.last_mut() {
+            Some(segment) if segment.kind == kind => {
+                segment.text.push(' ');
+                segment.text.push_str(word);
+            }
+            _ => segments.push(DiffSegment { kind, text: word.to_string() }),
+        }
+    }
+
+    TextDiff { segments, removed_words, added_words }
+}
+
+/// LCS over the part of both texts that differs
+fn diff_middle<'a>(
+    old: &[&'a str],
+    old_keys: &[String],
+    new: &[&'a str],
+    new_keys: &[String],
+) -> Vec<(DiffKind, &'a str)> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1) * (m + 1) > MAX_DIFF_CELLS {
+        return old.iter().map(|w| (DiffKind::Removed, *w))
+            .chain(new.iter().map(|w| (DiffKind::Added, *w)))
+            .collect();
+    }
+
+    // lcs[i][j] = LCS length of old[i..] and new[j..]
+    let width = m + 1;
+    let mut lcs = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * width + j] = if old_keys[i] == new_keys[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_keys[i] == new_keys[j] {
+            ops.push((DiffKind::Equal, new[j]));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            ops.push((DiffKind::Removed, old[i]));
+            i += 1;
+        } else {
+            ops.push((DiffKind::Added, new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|w| (DiffKind::Removed, *w)));
+    ops.extend(new[j..].iter().map(|w| (DiffKind::Added, *w)));
+    ops
+}
+
+fn match_key(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(diff: &TextDiff) -> Vec<(DiffKind, &str)> {
+        diff.segments.iter().map(|s| (s.kind, s.text.as_str())).collect()
+    }
+
+    #[test]
+    fn identical_text_is_one_equal_segment() {
+        let diff = diff_words("so we  meet\ton Monday", "so we meet on Monday");
+        assert_eq!(segments(&diff), [(DiffKind::Equal, "so we meet on Monday")]);
+        assert_eq!((diff.removed_words, diff.added_words), (0, 0));
+
+        let empty = diff_words("", "");
+        assert!(empty.segments.is_empty());
+    }
+
+    #[test]
+    fn case_and_punctuation_changes_are_not_edits() {
+        let diff = diff_words("so we meet on monday", "So we meet on Monday.");
+        // Matched words are shown as cleanup left them
+        assert_eq!(segments(&diff), [(DiffKind::Equal, "So we meet on Monday.")]);
+    }
+
+    #[test]
+    fn removed_fillers() {
+        let diff = diff_words("um so we uh meet on Monday", "so we meet on Monday");
+        assert_eq!(segments(&diff), [
+            (DiffKind::Removed, "um"),
+            (DiffKind::Equal, "so we"),
+            (DiffKind::Removed, "uh"),
+            (DiffKind::Equal, "meet on Monday"),
+        ]);
+        assert_eq!((diff.removed_words, diff.added_words), (2, 0));
+
+        let all_gone = diff_words("um uh", "");
+        assert_eq!(segments(&all_gone), [(DiffKind::Removed, "um uh")]);
+    }
+
+    #[test]
+    fn inserted_words() {
+        let diff = diff_words("meet Monday", "we meet on Monday at noon");
+        assert_eq!(segments(&diff), [
+            (DiffKind::Added, "we"),
+            (DiffKind::Equal, "meet"),
+            (DiffKind::Added, "on"),
+            (DiffKind::Equal, "Monday"),
+            (DiffKind::Added, "at noon"),
+        ]);
+        assert_eq!((diff.removed_words, diff.added_words), (0, 4));
+
+        let from_nothing = diff_words("", "hello there");
+        assert_eq!(segments(&from_nothing), [(DiffKind::Added, "hello there")]);
+    }
+
+    #[test]
+    fn replaced_words_are_removed_then_added() {
+        let diff = diff_words("we meet on Tuesday I mean Monday", "we meet on Monday");
+        assert_eq!(segments(&diff), [
+            (DiffKind::Equal, "we meet on"),
+            (DiffKind::Removed, "Tuesday I mean"),
+            (DiffKind::Equal, "Monday"),
+        ]);
+
+        let diff = diff_words("the red car", "the blue car");
+        assert_eq!(segments(&diff), [
+            (DiffKind::Equal, "the"),
+            (DiffKind::Removed, "red"),
+            (DiffKind::Added, "blue"),
+            (DiffKind::Equal, "car"),
+        ]);
+        assert_eq!((diff.removed_words, diff.added_words), (1, 1));
+    }
+
+    #[test]
+    fn arabic_and_mixed_script_words() {
+        // Arabic punctuation is trimmed like Latin punctuation
+        let diff = diff_words("يعني الاجتماع يوم الاثنين", "الاجتماع يوم الاثنين؟");
+        assert_eq!(segments(&diff), [
+            (DiffKind::Removed, "يعني"),
+            (DiffKind::Equal, "الاجتماع يوم الاثنين؟"),
+        ]);
+
+        let diff = diff_words("أرسل الـ email إلى Sara", "أرسل البريد إلى Sara");
+        assert_eq!(segments(&diff), [
+            (DiffKind::Equal, "أرسل"),
+            (DiffKind::Removed, "الـ email"),
+            (DiffKind::Added, "البريد"),
+            (DiffKind::Equal, "إلى Sara"),
+        ]);
+        assert_eq!((diff.removed_words, diff.added_words), (2, 1));
+    }
+
+    #[test]
+    fn very_long_edits_fall_back_to_one_replacement() {
+        let before: Vec<String> = (0..5000).map(|i| format!("a{}", i)).collect();
+        let after: Vec<String> = (0..5000).map(|i| format!("b{}", i)).collect();
+        let diff = diff_words(&format!("start {} end", before.join(" ")), &format!("start {} end", after.join(" ")));
+        let kinds: Vec<DiffKind> = diff.segments.iter().map(|s| s.kind).collect();
+        assert_eq!(kinds, [DiffKind::Equal, DiffKind::Removed, DiffKind::Added, DiffKind::Equal]);
+        assert_eq!((diff.removed_words, diff.added_words), (5000, 5000));
+    }
+}
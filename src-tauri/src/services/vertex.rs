@@ -0,0 +1,330 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::services::settings::VertexSettings;
+
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Lifetime requested for the signed assertion (Google's maximum)
+const ASSERTION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Refresh the access token this long before it actually expires
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// The fields of a Google service-account JSON key that token exchange needs
+#[derive(Debug, Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    private_key_id: Option<String>,
+    project_id: Option<String>,
+    token_uri: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+/// Service-account credentials for Vertex AI. Signs a JWT assertion, exchanges
+/// it for an OAuth access token and caches the token until shortly before expiry.
+pub struct VertexAuth {
+    key: ServiceAccountKey,
+    encoding_key: EncodingKey,
+    settings: VertexSettings,
+    token: Mutex<Option<CachedToken>>,
+}
+
+impl VertexAuth {
+    pub fn from_settings(settings: &VertexSettings) -> Result<Self> {
+        let path = settings.credentials_path.as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No service-account file configured for Vertex AI"))?;
+
+        let content = std::fs::read_to_string(Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to read service-account file: {}", e))?;
+        let key: ServiceAccountKey = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid service-account file: {}", e))?;
+        let encoding_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Invalid private key in service-account file: {}", e))?;
+
+        if settings.project_id.is_none() && key.project_id.is_none() {
+            anyhow::bail!("No Vertex AI project ID configured and none found in the service-account file");
+        }
+
+        Ok(Self {
+            key,
+            encoding_key,
+            settings: settings.clone(),
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Whether these credentials were built from the given settings
+    pub fn matches(&self, settings: &VertexSettings) -> bool {
+        self.settings == *settings
+    }
+
+    /// Regional `generateContent` URL for a publisher model
+    pub fn generate_content_url(&self, model: &str) -> String {
+        let region = self.settings.region.as_str();
+        let project = self.settings.project_id.as_deref()
+            .or(self.key.project_id.as_deref())
+            .unwrap_or_default();
+
+        let base = match self.settings.endpoint.as_deref() {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None if region == "global" => "https://aiplatform.googleapis.com".to_string(),
+            None => format!("https://{}-aiplatform.googleapis.com", region),
+        };
+
+        format!(
+            "{}/v1/projects/{}/locations/{}/publishers/google/models/{}:generateContent",
+            base, project, region, model
+        )
+    }
+
+    /// Return a cached access token, or exchange a fresh assertion for one
+    pub async fn access_token(&self, client: &Client) -> Result<String> {
+        if let Some(token) = self.token.lock().map_err(|e| anyhow::anyhow!(e.to_string()))?.as_ref() {
+            if Instant::now() + EXPIRY_MARGIN < token.expires_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let assertion = self.signed_assertion()?;
+        let token_uri = self.token_uri();
+
+        let response = client
+            .post(token_uri)
+            .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", assertion.as_str())])
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Network error while fetching Vertex AI token: {}", e))?;
+
+        let status = response.status();
+        let response_text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            anyhow::bail!("Vertex AI token exchange failed ({}): {}", status.as_u16(), response_text);
+        }
+
+        let token: TokenResponse = serde_json::from_str(&response_text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse Vertex AI token response: {}", e))?;
+
+        let access_token = token.access_token.clone();
+        *self.token.lock().map_err(|e| anyhow::anyhow!(e.to_string()))? = Some(CachedToken {
+            access_token: token.access_token,
+            expires_at: Instant::now() + Duration::from_secs(token.expires_in),
+        });
+
+        Ok(access_token)
+    }
+
+    fn token_uri(&self) -> &str {
+        self.key.token_uri.as_deref().unwrap_or(DEFAULT_TOKEN_URI)
+    }
+
+    fn signed_assertion(&self) -> Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = Claims {
+            iss: &self.key.client_email,
+            scope: CLOUD_PLATFORM_SCOPE,
+            aud: self.token_uri(),
+            iat: now,
+            exp: now + ASSERTION_LIFETIME.as_secs(),
+        };
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = self.key.private_key_id.clone();
+
+        Ok(jsonwebtoken::encode(&header, &claims, &self.encoding_key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::test_server::TestServer;
+    use jsonwebtoken::{DecodingKey, Validation};
+
+    /// Throwaway key pair, only ever used by these tests
+    const PRIVATE_KEY: &str = include_str!("testdata/vertex_test_key.pem");
+    const PUBLIC_KEY: &str = include_str!("testdata/vertex_test_key.pub.pem");
+
+    #[derive(Debug, Deserialize)]
+    struct DecodedClaims {
+        iss: String,
+        scope: String,
+        aud: String,
+        iat: u64,
+        exp: u64,
+    }
+
+    /// Write a service-account file pointing its token_uri at `token_uri`
+    fn credentials(dir: &tempfile::TempDir, token_uri: &str, project_id: Option<&str>) -> VertexSettings {
+        let path = dir.path().join("service-account.json");
+        let key = serde_json::json!({
+            "client_email": "visper@test-project.iam.gserviceaccount.com",
+            "private_key": PRIVATE_KEY,
+            "private_key_id": "key-1",
+            "project_id": project_id,
+            "token_uri": token_uri,
+        });
+        std::fs::write(&path, key.to_string()).unwrap();
+        VertexSettings {
+            credentials_path: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn token(access_token: &str, expires_in: u64) -> (u16, String) {
+        (200, serde_json::json!({ "access_token": access_token, "expires_in": expires_in, "token_type": "Bearer" }).to_string())
+    }
+
+    fn client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    /// The `assertion` field of a token exchange form body
+    fn assertion(body: &str) -> String {
+        body.split('&')
+            .find_map(|field| field.strip_prefix("assertion="))
+            .expect("assertion in form body")
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn exchanges_a_signed_assertion_for_a_token() {
+        let server = TestServer::start(vec![token("token-1", 3600)]);
+        let dir = tempfile::tempdir().unwrap();
+        let token_uri = format!("{}/token", server.url);
+        let auth = VertexAuth::from_settings(&credentials(&dir, &token_uri, Some("test-project"))).unwrap();
+
+        assert_eq!(auth.access_token(&client()).await.unwrap(), "token-1");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].line, "POST /token HTTP/1.1");
+        assert_eq!(requests[0].header("content-type"), Some("application/x-www-form-urlencoded"));
+        assert!(requests[0].body.contains("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer"));
+
+        let jwt = assertion(&requests[0].body);
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[token_uri.as_str()]);
+        let decoded = jsonwebtoken::decode::<DecodedClaims>(
+            &jwt,
+            &DecodingKey::from_rsa_pem(PUBLIC_KEY.as_bytes()).unwrap(),
+            &validation,
+        ).expect("assertion signed with the service-account key");
+
+        assert_eq!(decoded.header.kid.as_deref(), Some("key-1"));
+        assert_eq!(decoded.claims.iss, "visper@test-project.iam.gserviceaccount.com");
+        assert_eq!(decoded.claims.scope, CLOUD_PLATFORM_SCOPE);
+        assert_eq!(decoded.claims.aud, token_uri);
+        assert_eq!(decoded.claims.exp - decoded.claims.iat, ASSERTION_LIFETIME.as_secs());
+    }
+
+    #[tokio::test]
+    async fn token_is_cached_until_near_expiry() {
+        let server = TestServer::start(vec![token("token-1", 3600), token("token-2", 3600)]);
+        let dir = tempfile::tempdir().unwrap();
+        let auth = VertexAuth::from_settings(&credentials(&dir, &format!("{}/token", server.url), Some("p"))).unwrap();
+        let client = client();
+
+        assert_eq!(auth.access_token(&client).await.unwrap(), "token-1");
+        assert_eq!(auth.access_token(&client).await.unwrap(), "token-1");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expiring_token_is_refreshed() {
+        // Expires within the refresh margin, so it's only good for the request that fetched it
+        let server = TestServer::start(vec![token("token-1", 30), token("token-2", 3600)]);
+        let dir = tempfile::tempdir().unwrap();
+        let auth = VertexAuth::from_settings(&credentials(&dir, &format!("{}/token", server.url), Some("p"))).unwrap();
+        let client = client();
+
+        assert_eq!(auth.access_token(&client).await.unwrap(), "token-1");
+        assert_eq!(auth.access_token(&client).await.unwrap(), "token-2");
+        assert_eq!(auth.access_token(&client).await.unwrap(), "token-2");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        // Each exchange signs its own assertion
+        assert!(!assertion(&requests[1].body).is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_exchange_is_reported_and_not_cached() {
+        let server = TestServer::start(vec![
+            (400, r#"{"error":"invalid_grant"}"#.to_string()),
+            token("token-1", 3600),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let auth = VertexAuth::from_settings(&credentials(&dir, &format!("{}/token", server.url), Some("p"))).unwrap();
+        let client = client();
+
+        let err = auth.access_token(&client).await.unwrap_err().to_string();
+        assert!(err.contains("token exchange failed (400)"), "{}", err);
+        assert!(err.contains("invalid_grant"), "{}", err);
+        assert_eq!(auth.access_token(&client).await.unwrap(), "token-1");
+    }
+
+    #[test]
+    fn generate_content_url_honours_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = credentials(&dir, DEFAULT_TOKEN_URI, Some("key-project"));
+
+        let auth = VertexAuth::from_settings(&settings).unwrap();
+        assert_eq!(
+            auth.generate_content_url("gemini-2.5-flash"),
+            "https://us-central1-aiplatform.googleapis.com/v1/projects/key-project/locations/us-central1/publishers/google/models/gemini-2.5-flash:generateContent"
+        );
+
+        settings.region = "global".to_string();
+        settings.project_id = Some("override".to_string());
+        let auth = VertexAuth::from_settings(&settings).unwrap();
+        assert_eq!(
+            auth.generate_content_url("m"),
+            "https://aiplatform.googleapis.com/v1/projects/override/locations/global/publishers/google/models/m:generateContent"
+        );
+
+        settings.endpoint = Some("http://127.0.0.1:9/".to_string());
+        let auth = VertexAuth::from_settings(&settings).unwrap();
+        assert_eq!(
+            auth.generate_content_url("m"),
+            "http://127.0.0.1:9/v1/projects/override/locations/global/publishers/google/models/m:generateContent"
+        );
+    }
+
+    #[test]
+    fn project_id_is_required() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = credentials(&dir, DEFAULT_TOKEN_URI, None);
+        let err = VertexAuth::from_settings(&settings).err().unwrap().to_string();
+        assert!(err.contains("No Vertex AI project ID"), "{}", err);
+
+        settings.project_id = Some("p".to_string());
+        assert!(VertexAuth::from_settings(&settings).is_ok());
+    }
+}
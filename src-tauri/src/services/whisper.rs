@@ -0,0 +1,138 @@
+use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use std::time::Duration;
+use crate::services::openai::file_extension;
+use crate::services::settings::WhisperSettings;
+
+#[derive(Debug, Deserialize)]
+struct InferenceResponse {
+    #[serde(default)]
+    text: String,
+    error: Option<String>,
+}
+
+/// Client for a local whisper.cpp server (`whisper-server`)
+pub struct WhisperService {
+    client: Client,
+    settings: WhisperSettings,
+}
+
+impl WhisperService {
+    pub fn new(client: Client, settings: WhisperSettings) -> Self {
+        Self { client, settings }
+    }
+
+    /// Plain transcription of the recording. `context` is sent as the prompt,
+    /// which the model treats as preceding text. Errors are formatted for display.
+    pub async fn transcribe(
+        &self,
+        audio_buffer: &[u8],
+        mime_type: &str,
+        context: Option<&str>,
+        timeout: Duration,
+    ) -> Result<String, String> {
+        let file = Part::bytes(audio_buffer.to_vec())
+            .file_name(format!("recording.{}", file_extension(mime_type)))
+            .mime_str(mime_type)
+            .map_err(|e| format!("Invalid audio type: {}", e))?;
+        let mut form = Form::new()
+            .part("file", file)
+            .text("response_format", "json");
+        if let Some(context) = context {
+            form = form.text("prompt", context.to_string());
+        }
+
+        let response = self.client
+            .post(&self.settings.url)
+            .timeout(timeout)
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| format!("Local Whisper is not reachable: {}", e))?;
+
+        let status = response.status();
+        let response_text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(format!("Local Whisper error ({}): {}", status.as_u16(), response_text));
+        }
+
+        let inference: InferenceResponse = serde_json::from_str(&response_text)
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+        if let Some(error) = inference.error {
+            return Err(format!("Local Whisper error: {}", error));
+        }
+        Ok(inference.text.trim().to_string())
+    }
+
+    pub fn update_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    pub fn update_settings(&mut self, settings: WhisperSettings) {
+        self.settings = settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::test_server::TestServer;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn service(url: &str) -> WhisperService {
+        let client = Client::builder().no_proxy().build().unwrap();
+        WhisperService::new(client, WhisperSettings { url: url.to_string() })
+    }
+
+    #[tokio::test]
+    async fn posts_the_recording_to_the_inference_url() {
+        let server = TestServer::start(vec![(200, r#"{"text": " Hello there.\n"}"#.to_string())]);
+        let whisper = service(&format!("{}/inference", server.url));
+
+        let text = whisper.transcribe(b"fake audio", "audio/wav", Some("Earlier text"), TIMEOUT).await;
+
+        assert_eq!(text.as_deref(), Ok("Hello there."));
+        let request = &server.requests()[0];
+        assert!(request.line.starts_with("POST /inference "), "{}", request.line);
+        assert_eq!(request.header("authorization"), None);
+        for expected in [
+            "name=\"file\"; filename=\"recording.wav\"",
+            "Content-Type: audio/wav",
+            "fake audio",
+            "name=\"response_format\"\r\n\r\njson\r\n",
+            "name=\"prompt\"\r\n\r\nEarlier text\r\n",
+        ] {
+            assert!(request.body.contains(expected), "missing {:?} in {}", expected, request.body);
+        }
+        // The server picks its own model
+        assert!(!request.body.contains("name=\"model\""));
+    }
+
+    #[tokio::test]
+    async fn errors_are_reported_as_local_whisper_errors() {
+        let server = TestServer::start(vec![
+            (500, "model not loaded".to_string()),
+            (200, r#"{"error": "failed to read audio"}"#.to_string()),
+            (200, "not json".to_string()),
+        ]);
+        let whisper = service(&format!("{}/inference", server.url));
+        let transcribe = || whisper.transcribe(b"fake audio", "audio/webm", None, TIMEOUT);
+
+        assert_eq!(transcribe().await, Err("Local Whisper error (500): model not loaded".to_string()));
+        assert_eq!(transcribe().await, Err("Local Whisper error: failed to read audio".to_string()));
+        let error = transcribe().await.unwrap_err();
+        assert!(error.starts_with("Failed to parse response:"), "{}", error);
+        assert!(!server.requests()[0].body.contains("name=\"prompt\""));
+    }
+
+    #[tokio::test]
+    async fn stopped_server_is_not_reachable() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let whisper = service(&format!("http://{}/inference", address));
+
+        let error = whisper.transcribe(b"fake audio", "audio/webm", None, TIMEOUT).await.unwrap_err();
+        assert!(error.starts_with("Local Whisper is not reachable:"), "{}", error);
+    }
+}
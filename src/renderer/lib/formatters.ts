@@ -73,3 +73,24 @@ export function groupByDate<T extends { createdAt: string }>(items: T[]): Map<st
 
   return groups;
 }
+
+// Markers the backend puts around search hits in snippets
+const HIGHLIGHT_START = '\u0002';
+const HIGHLIGHT_END = '\u0003';
+
+/**
+ * Split a search snippet into plain and highlighted parts
+ */
+export function highlightParts(snippet: string): { text: string; hit: boolean }[] {
+  const parts: { text: string; hit: boolean }[] = [];
+  for (const chunk of snippet.split(HIGHLIGHT_START)) {
+    const end = chunk.indexOf(HIGHLIGHT_END);
+    if (end === -1) {
+      if (chunk) parts.push({ text: chunk, hit: false });
+      continue;
+    }
+    parts.push({ text: chunk.slice(0, end), hit: true });
+    if (end + 1 < chunk.length) parts.push({ text: chunk.slice(end + 1), hit: false });
+  }
+  return parts;
+}
@@ -1,4 +1,4 @@
-import { invoke } from '@tauri-apps/api/core';
+import { convertFileSrc, invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import { writeText } from '@tauri-apps/plugin-clipboard-manager';
 import { getCurrentWindow } from '@tauri-apps/api/window';
@@ -8,6 +8,16 @@ interface TranscriptionResult {
   success: boolean;
   text?: string;
   error?: string;
+  isInaudible: boolean;
+  confidence?: number;
+  answerSuspected: boolean;
+  language?: string;
+  rawText?: string;
+  originalText?: string;
+  originalLanguage?: string;
+  provider?: Provider;
+  audioFile?: string;
+  mode?: DictationMode;
 }
 
 interface Transcription {
@@ -17,11 +27,159 @@ interface Transcription {
   tokensUsed: number | null;
   createdAt: string;
   isFavorite: number;
+  language: string | null;
+  provider: Provider | null;
+  rawText: string | null;
+  parentId: number | null;
+  rewriteMode: RewriteMode | null;
+  recordingId: number | null;
+  folderId: number | null;
+  mode: DictationMode | null;
+  deletedAt: string | null;
+  tags: string[];
+  // Search results only: the match, with hits marked for highlightParts()
+  snippet: string | null;
+}
+
+// A tag or folder with how many entries it holds
+interface Label {
+  id: number;
+  name: string;
+  count: number;
+}
+
+type SortKey = 'createdAt' | 'duration' | 'length' | 'relevance';
+
+// Unset filters match everything; dates are UTC, `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`
+interface HistoryQuery {
+  // Words and "phrases" must all match; word* matches by prefix, OR accepts either side, -word excludes
+  search?: string;
+  // Match search items inside words (at least three characters each), e.g. for Chinese or Japanese
+  substring?: boolean;
+  // Tolerate misrecognized words; results come back by similarity and `sort` is ignored
+  fuzzy?: boolean;
+  from?: string;
+  to?: string;
+  favoritesOnly?: boolean;
+  minDuration?: number;
+  maxDuration?: number;
+  language?: string;
+  mode?: DictationMode;
+  tagId?: number;
+  folderId?: number;
+  sort?: SortKey;
+  direction?: 'asc' | 'desc';
+  limit?: number;
+  // `nextCursor` of the previous page
+  cursor?: string;
+}
+
+interface Revision {
+  id: number;
+  transcriptionId: number;
+  text: string;
+  source: 'dictation' | 'rewrite' | 'retranscribe' | 'append' | 'edit' | 'restore';
+  replacedAt: string;
+}
+
+interface DiffSegment {
+  kind: 'equal' | 'removed' | 'added';
+  text: string;
+}
+
+interface TextDiff {
+  segments: DiffSegment[];
+  removedWords: number;
+  addedWords: number;
 }
 
 interface HistoryResult {
   items: Transcription[];
   total: number;
+  // Fuzzy search only compares a limited number of entries; more may match
+  totalIsLowerBound: boolean;
+  tagCounts: Label[];
+  nextCursor: string | null;
+}
+
+interface SemanticMatch extends Transcription {
+  similarity: number;
+}
+
+// Answer to a question about history; citations are ids of entries in sources
+interface HistoryAnswer {
+  answer: string;
+  citations: number[];
+  sources: Transcription[];
+  provider: string;
+}
+
+type DictationMode = 'clean' | 'summarize' | 'translate';
+
+type RewriteMode = 'formalize' | 'shorten' | 'bullets' | 'fixGrammar' | 'translate';
+
+type Provider = 'gemini' | 'vertex' | 'openai' | 'whisper';
+
+type AuthScheme = 'apiKey' | 'bearer' | 'header';
+
+interface GeminiSettings {
+  baseUrl: string;
+  authScheme: AuthScheme;
+  authHeader: string | null;
+}
+
+interface VertexSettings {
+  credentialsPath: string | null;
+  projectId: string | null;
+  region: string;
+  endpoint: string | null;
+}
+
+interface ContinuationSettings {
+  enabled: boolean;
+  contextChars: number | null;
+}
+
+interface AudioRetentionSettings {
+  enabled: boolean;
+  maxSizeMb: number;
+  maxAgeDays: number | null;
+  keepFavorites: boolean;
+}
+
+interface OpenAiSettings {
+  baseUrl: string;
+  model: string;
+}
+
+interface WhisperSettings {
+  url: string;
+}
+
+interface CircuitBreakerSettings {
+  failureThreshold: number;
+  cooldownSeconds: number;
+}
+
+interface NetworkSettings {
+  proxyUrl: string | null;
+  proxyUsername: string | null;
+  caBundlePath: string | null;
+  connectTimeoutSeconds: number;
+  timeoutSeconds: number;
+  timeoutPerAudioMinuteSeconds: number;
+}
+
+interface SafetySetting {
+  category: string;
+  threshold: string;
+}
+
+interface GenerationSettings {
+  temperature: number | null;
+  maxOutputTokens: number | null;
+  thinkingBudget: number | null;
+  safetySettings: SafetySetting[] | null;
 }
 
 interface Settings {
@@ -29,6 +187,25 @@ interface Settings {
   soundEnabled: boolean;
   firstLaunchComplete: boolean;
   hotkey: string;
+  mode: DictationMode;
+  targetLanguage: string;
+  keepOriginal: boolean;
+  semanticSearch: boolean;
+  trashRetentionDays: number | null;
+  continuation: ContinuationSettings;
+  audioRetention: AudioRetentionSettings;
+  preferredLanguage: string | null;
+  allowedLanguages: string[];
+  providers: Provider[];
+  gemini: GeminiSettings;
+  vertex: VertexSettings;
+  openai: OpenAiSettings;
+  whisper: WhisperSettings;
+  circuitBreaker: CircuitBreakerSettings;
+  network: NetworkSettings;
+  model: string;
+  generation: GenerationSettings;
+  modeOverrides: Partial<Record<DictationMode, GenerationSettings>>;
 }
 
 interface SetApiKeyResult {
@@ -41,15 +218,32 @@ interface TestApiResult {
   error?: string;
 }
 
+interface ModelInfo {
+  id: string;
+  displayName: string;
+  description: string;
+  inputTokenLimit: number | null;
+  outputTokenLimit: number | null;
+  thinking: boolean;
+  thinkingLimits: ThinkingLimits | null;
+}
+
+interface ThinkingLimits {
+  min: number;
+  max: number;
+  canDisable: boolean;
+}
+
 // Create the same API interface as Electron's preload
 export const visperAPI = {
   recording: {
-    sendAudioData: async (audioBuffer: ArrayBuffer): Promise<TranscriptionResult> => {
+    sendAudioData: async (audioBuffer: ArrayBuffer, continueFrom?: number | null): Promise<TranscriptionResult> => {
       // Convert ArrayBuffer to number array for Tauri serialization
       const uint8Array = new Uint8Array(audioBuffer);
       return invoke<TranscriptionResult>('send_audio_data', {
         audioBuffer: Array.from(uint8Array),
-        mimeType: "audio/wav"
+        mimeType: "audio/wav",
+        continueFrom: continueFrom ?? null
       });
     },
 
@@ -70,28 +264,153 @@ export const visperAPI = {
   },
 
   history: {
-    get: async (page: number, limit: number): Promise<HistoryResult> => {
-      return invoke<HistoryResult>('history_get', { page, limit });
+    query: async (query: HistoryQuery): Promise<HistoryResult> => {
+      return invoke<HistoryResult>('history_query', { query });
+    },
+
+    semanticSearch: async (query: string, k: number): Promise<SemanticMatch[]> => {
+      return invoke<SemanticMatch[]>('history_semantic_search', { query, k });
+    },
+
+    ask: async (question: string): Promise<HistoryAnswer> => {
+      return invoke<HistoryAnswer>('history_ask', { question });
+    },
+
+    languages: async (): Promise<string[]> => {
+      return invoke<string[]>('history_languages');
     },
 
-    search: async (query: string, page: number, limit: number): Promise<HistoryResult> => {
-      return invoke<HistoryResult>('history_search', { query, page, limit });
+    save: async (
+      text: string,
+      durationSeconds: number,
+      language?: string,
+      provider?: Provider,
+      rawText?: string,
+      audioFile?: string,
+      mode?: DictationMode
+    ): Promise<number> => {
+      return invoke<number>('history_save', {
+        text,
+        rawText: rawText || null,
+        durationSeconds,
+        language: language || null,
+        provider: provider || null,
+        mode: mode || null,
+        audioFile: audioFile || null
+      });
     },
 
-    save: async (text: string, durationSeconds: number): Promise<number> => {
-      return invoke<number>('history_save', { text, durationSeconds });
+    append: async (id: number, text: string, durationSeconds: number, rawText?: string): Promise<boolean> => {
+      return invoke<boolean>('history_append', { id, text, rawText: rawText || null, durationSeconds });
+    },
+
+    diff: async (id: number): Promise<TextDiff> => {
+      return invoke<TextDiff>('history_diff', { id });
+    },
+
+    update: async (id: number, text: string): Promise<boolean> => {
+      return invoke<boolean>('history_update', { id, text });
+    },
+
+    revisions: async (id: number): Promise<Revision[]> => {
+      return invoke<Revision[]>('history_revisions', { id });
+    },
+
+    revisionDiff: async (revisionId: number, compareTo?: number): Promise<TextDiff> => {
+      return invoke<TextDiff>('history_revision_diff', { revisionId, compareTo: compareTo ?? null });
+    },
+
+    restore: async (revisionId: number): Promise<boolean> => {
+      return invoke<boolean>('history_restore', { revisionId });
+    },
+
+    rewrite: async (id: number, mode: RewriteMode): Promise<Transcription> => {
+      return invoke<Transcription>('history_rewrite', { id, mode });
+    },
+
+    // Served by the backend's `recording` protocol, playable by an audio element
+    recordingUrl: (id: number): string => {
+      return convertFileSrc(String(id), 'recording');
+    },
+
+    retranscribe: async (id: number, provider: Provider, mode: DictationMode): Promise<Transcription> => {
+      return invoke<Transcription>('history_retranscribe', { id, provider, mode });
     },
 
     delete: async (id: number): Promise<boolean> => {
       return invoke<boolean>('history_delete', { id });
     },
 
-    clear: async (): Promise<void> => {
-      return invoke<void>('history_clear');
+    // Moves everything to the trash; restore the returned ids to undo
+    clear: async (): Promise<number[]> => {
+      return invoke<number[]>('history_clear');
     },
 
     toggleFavorite: async (id: number): Promise<boolean> => {
       return invoke<boolean>('toggle_favorite', { id });
+    },
+
+    // Creates the tag if no tag has that name yet
+    addTag: async (id: number, name: string): Promise<number> => {
+      return invoke<number>('history_add_tag', { id, name });
+    },
+
+    removeTag: async (id: number, tagId: number): Promise<boolean> => {
+      return invoke<boolean>('history_remove_tag', { id, tagId });
+    },
+
+    setFolder: async (id: number, folderId: number | null): Promise<boolean> => {
+      return invoke<boolean>('history_set_folder', { id, folderId });
+    }
+  },
+
+  tags: {
+    list: async (): Promise<Label[]> => {
+      return invoke<Label[]>('tags_list');
+    },
+
+    create: async (name: string): Promise<number> => {
+      return invoke<number>('tag_create', { name });
+    },
+
+    rename: async (id: number, name: string): Promise<boolean> => {
+      return invoke<boolean>('tag_rename', { id, name });
+    },
+
+    delete: async (id: number): Promise<boolean> => {
+      return invoke<boolean>('tag_delete', { id });
+    }
+  },
+
+  folders: {
+    list: async (): Promise<Label[]> => {
+      return invoke<Label[]>('folders_list');
+    },
+
+    create: async (name: string): Promise<number> => {
+      return invoke<number>('folder_create', { name });
+    },
+
+    rename: async (id: number, name: string): Promise<boolean> => {
+      return invoke<boolean>('folder_rename', { id, name });
+    },
+
+    delete: async (id: number): Promise<boolean> => {
+      return invoke<boolean>('folder_delete', { id });
+    }
+  },
+
+  trash: {
+    list: async (): Promise<Transcription[]> => {
+      return invoke<Transcription[]>('trash_list');
+    },
+
+    restore: async (ids: number[]): Promise<number> => {
+      return invoke<number>('trash_restore', { ids });
+    },
+
+    empty: async (): Promise<number> => {
+      return invoke<number>('trash_empty');
     }
   },
 
@@ -116,8 +435,28 @@ export const visperAPI = {
       return invoke<void>('delete_api_key');
     },
 
-    testApi: async (key?: string): Promise<TestApiResult> => {
-      return invoke<TestApiResult>('test_api', { key: key || null });
+    testApi: async (key?: string, model?: string, provider?: Provider): Promise<TestApiResult> => {
+      return invoke<TestApiResult>('test_api', { key: key || null, model: model || null, provider: provider || null });
+    },
+
+    getOpenAiApiKey: async (): Promise<string | null> => {
+      return invoke<string | null>('get_openai_api_key');
+    },
+
+    setOpenAiApiKey: async (key: string): Promise<SetApiKeyResult> => {
+      return invoke<SetApiKeyResult>('set_openai_api_key', { key });
+    },
+
+    deleteOpenAiApiKey: async (): Promise<void> => {
+      return invoke<void>('delete_openai_api_key');
+    },
+
+    setProxyPassword: async (password: string | null): Promise<void> => {
+      return invoke<void>('set_proxy_password', { password });
+    },
+
+    listModels: async (refresh = false): Promise<ModelInfo[]> => {
+      return invoke<ModelInfo[]>('list_models', { refresh });
     }
   },
 
@@ -176,4 +515,4 @@ declare global {
   }
 }
 
-export type { TranscriptionResult, HistoryResult, Settings, SetApiKeyResult, TestApiResult, Transcription };
+export type { TranscriptionResult, HistoryResult, HistoryQuery, SemanticMatch, HistoryAnswer, SortKey, Label, Revision, TextDiff, DiffSegment, Settings, DictationMode, RewriteMode, ContinuationSettings, AudioRetentionSettings, GenerationSettings, Provider, AuthScheme, GeminiSettings, VertexSettings, OpenAiSettings, WhisperSettings, CircuitBreakerSettings, NetworkSettings, SetApiKeyResult, TestApiResult, ModelInfo, ThinkingLimits, Transcription };